@@ -0,0 +1,674 @@
+//! Serving application assets to the webview.
+//!
+//! An [`AssetResolver`] maps a request path to an [`Asset`]. [`serve()`] turns the result into
+//! an HTTP style response, including `HEAD` and single-range requests, so every transport
+//! answers the same way.
+//!
+//! Resolvers provided here:
+//!
+//! * [`Directory`] serves files below a directory on disk.
+//! * [`MemoryAssets`] serves a map built at runtime.
+//! * [`EmbeddedAssets`] serves files baked in by [`embed_assets!`](../macro.embed_assets.html).
+//! * [`Layered`] asks several resolvers in turn, e.g. a dev directory over embedded assets.
+//! * [`SpaFallback`] answers unknown routes with `index.html`.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A resolved asset.
+#[derive(Clone, Debug)]
+pub struct Asset {
+    pub data: Cow<'static, [u8]>,
+    pub mime_type: Cow<'static, str>,
+    /// `Content-Encoding` of `data` when it is precompressed.
+    pub encoding: Option<&'static str>,
+    /// The uncompressed data, for clients whose `Accept-Encoding` rules out `encoding`.
+    pub identity: Option<Cow<'static, [u8]>>,
+    /// Entity tag, including the quotes. Enables `If-None-Match` revalidation.
+    pub etag: Option<Cow<'static, str>>,
+    /// Extra response headers, e.g. `Cache-Control` or `Content-Security-Policy`.
+    pub headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl Asset {
+    /// Creates an asset, guessing the MIME type from `path`.
+    pub fn new<D: Into<Cow<'static, [u8]>>>(path: &str, data: D) -> Self {
+        Asset {
+            data: data.into(),
+            mime_type: Cow::Borrowed(mime_type(path)),
+            encoding: None,
+            identity: None,
+            etag: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Adds a response header.
+    pub fn header<N: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(mut self, name: N, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// Maps request paths to assets.
+///
+/// Closures of the form `Fn(&str) -> Option<Asset>` are resolvers too.
+pub trait AssetResolver: Send + Sync {
+    /// `path` is the percent-decoded request path, starting with `/`, without query or fragment.
+    fn resolve(&self, path: &str) -> Option<Asset>;
+}
+
+impl<F: Fn(&str) -> Option<Asset> + Send + Sync> AssetResolver for F {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        self(path)
+    }
+}
+
+impl<R: AssetResolver + ?Sized> AssetResolver for std::sync::Arc<R> {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        (**self).resolve(path)
+    }
+}
+
+/// Maps directory paths (ending in `/`) to their `index.html`.
+fn with_index(path: &str) -> Cow<'_, str> {
+    if path.ends_with('/') {
+        Cow::Owned(format!("{}index.html", path))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Serves the files below a directory on disk, read on every request.
+///
+/// Only plain path segments are accepted: `..`, backslashes, drive letters and the like are
+/// rejected, and files reached through links that leave the directory are not served.
+pub struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Directory { root: root.as_ref().to_owned() }
+    }
+
+    /// The file `path` refers to, if it stays inside the root.
+    fn file(&self, path: &str) -> Option<PathBuf> {
+        let mut file = self.root.clone();
+        for segment in with_index(path).split('/') {
+            if segment.is_empty() || segment == "." {
+                continue
+            }
+            if segment == ".." || segment.contains(['\\', ':', '\0']) {
+                return None
+            }
+            // Anything but a plain name, e.g. a root or prefix on some platform.
+            let mut components = Path::new(segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => file.push(segment),
+                _ => return None
+            }
+        }
+        if file.is_dir() {
+            file.push("index.html");
+        }
+        let root = self.root.canonicalize().ok()?;
+        let file = file.canonicalize().ok()?;
+        if file.starts_with(&root) && file.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+}
+
+impl AssetResolver for Directory {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        let file = self.file(path)?;
+        let data = fs::read(&file).ok()?;
+        Some(Asset::new(file.to_str().unwrap_or(""), data))
+    }
+}
+
+/// Serves assets from a map built at runtime.
+#[derive(Clone, Default)]
+pub struct MemoryAssets {
+    files: HashMap<String, Asset>,
+}
+
+impl MemoryAssets {
+    pub fn new() -> Self {
+        MemoryAssets::default()
+    }
+
+    /// Adds `data` at `path` (starting with `/`), guessing the MIME type from the path.
+    pub fn insert<D: Into<Cow<'static, [u8]>>>(&mut self, path: &str, data: D) -> &mut Self {
+        self.insert_asset(path, Asset::new(path, data))
+    }
+
+    /// Adds a fully specified asset at `path`.
+    pub fn insert_asset(&mut self, path: &str, asset: Asset) -> &mut Self {
+        self.files.insert(path.to_owned(), asset);
+        self
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<Asset> {
+        self.files.remove(path)
+    }
+}
+
+impl AssetResolver for MemoryAssets {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        self.files.get(&*with_index(path)).cloned()
+    }
+}
+
+/// Asks each resolver in the order they were added and returns the first hit.
+///
+/// ```ignore
+/// Layered::new().layer(Directory::new("overrides")).layer(embed_assets!("dist"))
+/// ```
+#[derive(Default)]
+pub struct Layered {
+    layers: Vec<Box<dyn AssetResolver>>,
+}
+
+impl Layered {
+    pub fn new() -> Self {
+        Layered::default()
+    }
+
+    /// Adds a layer below the existing ones.
+    pub fn layer<R: AssetResolver + 'static>(mut self, resolver: R) -> Self {
+        self.layers.push(Box::new(resolver));
+        self
+    }
+}
+
+impl AssetResolver for Layered {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        self.layers.iter().find_map(|r| r.resolve(path))
+    }
+}
+
+/// Single page application fallback: paths the inner resolver does not know and whose last
+/// segment has no file extension are client-side routes, answered with the index page.
+/// Missing files such as `/app.js` still get a 404.
+pub struct SpaFallback<R> {
+    inner: R,
+    index: String,
+}
+
+impl<R: AssetResolver> SpaFallback<R> {
+    /// Falls back to `/index.html`.
+    pub fn new(inner: R) -> Self {
+        SpaFallback {
+            inner,
+            index: "/index.html".to_owned(),
+        }
+    }
+
+    /// Falls back to `index` instead.
+    pub fn index(mut self, index: &str) -> Self {
+        self.index = index.to_owned();
+        self
+    }
+}
+
+impl<R: AssetResolver> AssetResolver for SpaFallback<R> {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        self.inner.resolve(path).or_else(|| {
+            let name = path.rsplit('/').next().unwrap_or("");
+            if name.contains('.') {
+                None
+            } else {
+                self.inner.resolve(&self.index)
+            }
+        })
+    }
+}
+
+/// A file baked into the binary by [`embed_assets!`](../macro.embed_assets.html).
+#[doc(hidden)]
+pub struct EmbeddedFile {
+    pub path: &'static str,
+    pub mime_type: &'static str,
+    pub encoding: Option<&'static str>,
+    pub etag: &'static str,
+    pub data: &'static [u8],
+    pub identity: Option<&'static [u8]>,
+}
+
+/// Assets baked into the binary by [`embed_assets!`](../macro.embed_assets.html).
+#[derive(Clone, Copy)]
+pub struct EmbeddedAssets {
+    files: &'static [EmbeddedFile],
+}
+
+impl EmbeddedAssets {
+    /// `files` must be sorted by path.
+    #[doc(hidden)]
+    pub const fn new(files: &'static [EmbeddedFile]) -> Self {
+        EmbeddedAssets { files }
+    }
+
+    /// Paths of all embedded files.
+    pub fn paths(&self) -> impl Iterator<Item = &'static str> {
+        self.files.iter().map(|f| f.path)
+    }
+}
+
+impl AssetResolver for EmbeddedAssets {
+    fn resolve(&self, path: &str) -> Option<Asset> {
+        let path = with_index(path);
+        let file = &self.files[self.files.binary_search_by(|f| f.path.cmp(&path)).ok()?];
+        Some(Asset {
+            data: Cow::Borrowed(file.data),
+            mime_type: Cow::Borrowed(file.mime_type),
+            encoding: file.encoding,
+            identity: file.identity.map(Cow::Borrowed),
+            etag: Some(Cow::Borrowed(file.etag)),
+            headers: Vec::new(),
+        })
+    }
+}
+
+/// Guesses the MIME type from the file extension. Unknown types are `application/octet-stream`.
+pub fn mime_type(path: &str) -> &'static str {
+    let ext = match path.rsplit('/').next().and_then(|name| name.rsplit_once('.')) {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return "application/octet-stream"
+    };
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    pub reason: &'static str,
+    pub headers: Vec<(Cow<'static, str>, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn status(status: u16, reason: &'static str) -> Self {
+        Response {
+            status,
+            reason,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header<N: Into<Cow<'static, str>>>(&mut self, name: N, value: String) {
+        self.headers.push((name.into(), value));
+    }
+
+    /// Headers as a CRLF separated block.
+    pub fn header_block(&self) -> String {
+        self.headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
+}
+
+/// Extracts the decoded asset path from a request URI below `origin`.
+pub(crate) fn request_path(uri: &str, origin: &str) -> String {
+    let rest = uri.strip_prefix(origin).unwrap_or(uri);
+    let rest = rest.split(['?', '#']).next().unwrap_or("");
+    let rest = urlencoding::decode(rest).map(Cow::into_owned).unwrap_or_else(|_| rest.to_owned());
+    if rest.starts_with('/') {
+        rest
+    } else {
+        format!("/{}", rest)
+    }
+}
+
+/// Parses a single `bytes=` range against a body of `len` bytes.
+///
+/// Returns `None` when the header should be ignored (absent, malformed or multiple ranges),
+/// `Some(Err(()))` when the range is not satisfiable and `Some(Ok((first, last)))` otherwise.
+fn parse_range(header: Option<&str>, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let spec = header?.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = if first.is_empty() {
+        let suffix: usize = last.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()))
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let first: usize = first.parse().ok()?;
+        let last = if last.is_empty() { usize::MAX } else { last.parse().ok()? };
+        if first > last {
+            return None
+        }
+        if first >= len {
+            return Some(Err(()))
+        }
+        (first, last.min(len - 1))
+    };
+    Some(Ok(range))
+}
+
+pub(crate) struct Request<'r> {
+    pub method: &'r str,
+    /// Decoded path, see [`request_path()`].
+    pub path: &'r str,
+    pub range: Option<&'r str>,
+    pub if_none_match: Option<&'r str>,
+    pub accept_encoding: Option<&'r str>,
+}
+
+/// Answers `req` from `resolver`.
+pub(crate) fn serve(resolver: &dyn AssetResolver, req: &Request) -> Response {
+    let head = req.method.eq_ignore_ascii_case("HEAD");
+    if !head && !req.method.eq_ignore_ascii_case("GET") {
+        let mut res = Response::status(405, "Method Not Allowed");
+        res.header("Allow", "GET, HEAD".to_owned());
+        return res
+    }
+    let asset = match resolver.resolve(req.path) {
+        Some(asset) => asset,
+        None => return Response::status(404, "Not Found")
+    };
+    if let (Some(etag), Some(tags)) = (&asset.etag, req.if_none_match) {
+        if tags.split(',').any(|t| t.trim() == etag || t.trim() == "*") {
+            let mut res = Response::status(304, "Not Modified");
+            res.header("ETag", etag.to_string());
+            return res
+        }
+    }
+
+    // Clients that cannot decode the data get the identity copy, where there is one.
+    let negotiated = asset.encoding.is_some() && asset.identity.is_some();
+    let (data, encoding) = match (asset.encoding, asset.identity) {
+        (Some(encoding), Some(identity)) if !accepts(req.accept_encoding, encoding) => (identity, None),
+        (encoding, _) => (asset.data, encoding)
+    };
+    // Ranges of compressed data are meaningless to the page.
+    let range = if encoding.is_some() { None } else { req.range };
+    let len = data.len();
+    let mut res = match parse_range(range, len) {
+        Some(Ok((first, last))) => {
+            let mut res = Response::status(206, "Partial Content");
+            res.header("Content-Range", format!("bytes {}-{}/{}", first, last, len));
+            res.body = data[first..=last].to_vec();
+            res
+        }
+        Some(Err(())) => {
+            let mut res = Response::status(416, "Range Not Satisfiable");
+            res.header("Content-Range", format!("bytes */{}", len));
+            return res
+        }
+        None => {
+            let mut res = Response::status(200, "OK");
+            res.body = data.into_owned();
+            res
+        }
+    };
+    res.header("Content-Type", asset.mime_type.into_owned());
+    res.header("Content-Length", res.body.len().to_string());
+    match encoding {
+        Some(encoding) => res.header("Content-Encoding", encoding.to_owned()),
+        None => res.header("Accept-Ranges", "bytes".to_owned())
+    }
+    if negotiated {
+        res.header("Vary", "Accept-Encoding".to_owned());
+    }
+    if let Some(etag) = asset.etag {
+        res.header("ETag", etag.into_owned());
+        if !asset.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Cache-Control")) {
+            res.header("Cache-Control", "no-cache".to_owned());
+        }
+    }
+    for (name, value) in asset.headers {
+        res.header(name, value.into_owned());
+    }
+    if head {
+        res.body.clear();
+    }
+    res
+}
+
+/// Whether an `Accept-Encoding` header allows `encoding`. Without the header anything goes.
+fn accepts(header: Option<&str>, encoding: &str) -> bool {
+    let header = match header {
+        Some(header) => header,
+        None => return true
+    };
+    let mut wildcard = false;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return q > 0.0
+        }
+        if name == "*" {
+            wildcard = q > 0.0;
+        }
+    }
+    wildcard
+}
+
+/// Origin the assets of `scheme` are served from.
+///
+/// WebView2 only lets the host intercept http(s) requests, so `app://index.html` is loaded
+/// as `https://app.localhost/index.html`.
+pub(crate) fn origin(scheme: &str) -> String {
+    format!("https://{}.localhost", scheme)
+}
+
+/// Rewrites `<scheme>://path` to the same path below `origin`. Other URLs are returned as is.
+pub(crate) fn rewrite_url(url: &str, scheme: &str, origin: &str) -> String {
+    match url.strip_prefix(scheme).and_then(|rest| rest.strip_prefix("://")) {
+        Some(path) => format!("{}/{}", origin, path.trim_start_matches('/')),
+        None => url.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get<'r>(path: &'r str, range: Option<&'r str>) -> Request<'r> {
+        Request { method: "GET", path, range, if_none_match: None, accept_encoding: None }
+    }
+
+    fn header<'r>(res: &'r Response, name: &str) -> Option<&'r str> {
+        res.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn memory() -> MemoryAssets {
+        let mut m = MemoryAssets::new();
+        m.insert("/index.html", &b"<html></html>"[..]).insert("/app.js", &b"0123456789"[..]);
+        m
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(mime_type("/a/b.HTML"), "text/html; charset=utf-8");
+        assert_eq!(mime_type("/x.wasm"), "application/wasm");
+        assert_eq!(mime_type("/dir.d/file"), "application/octet-stream");
+        assert_eq!(mime_type("/x.unknown"), "application/octet-stream");
+    }
+
+    #[test]
+    fn request_paths() {
+        let origin = "https://app.localhost";
+        assert_eq!(request_path("https://app.localhost/a%20b.js?v=1#x", origin), "/a b.js");
+        assert_eq!(request_path("https://app.localhost", origin), "/");
+        assert_eq!(request_path("https://app.localhost/%2e%2e/secret", origin), "/../secret");
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(None, 10), None);
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), Some(Ok((2, 4))));
+        assert_eq!(parse_range(Some("bytes=2-"), 10), Some(Ok((2, 9))));
+        assert_eq!(parse_range(Some("bytes=5-100"), 10), Some(Ok((5, 9))));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), Some(Ok((7, 9))));
+        assert_eq!(parse_range(Some("bytes=-30"), 10), Some(Ok((0, 9))));
+        assert_eq!(parse_range(Some("bytes=10-"), 10), Some(Err(())));
+        assert_eq!(parse_range(Some("bytes=0-1,4-5"), 10), None);
+        assert_eq!(parse_range(Some("bytes=4-2"), 10), None);
+        assert_eq!(parse_range(Some("items=0-1"), 10), None);
+    }
+
+    #[test]
+    fn statuses() {
+        let m = memory();
+        let res = serve(&m, &get("/app.js", None));
+        assert_eq!((res.status, res.body.len()), (200, 10));
+        assert_eq!(header(&res, "Content-Type"), Some("text/javascript; charset=utf-8"));
+        assert_eq!(header(&res, "Accept-Ranges"), Some("bytes"));
+
+        let res = serve(&m, &get("/app.js", Some("bytes=2-4")));
+        assert_eq!((res.status, &res.body[..]), (206, &b"234"[..]));
+        assert_eq!(header(&res, "Content-Range"), Some("bytes 2-4/10"));
+
+        let res = serve(&m, &get("/app.js", Some("bytes=20-")));
+        assert_eq!(res.status, 416);
+        assert_eq!(header(&res, "Content-Range"), Some("bytes */10"));
+
+        assert_eq!(serve(&m, &get("/missing.js", None)).status, 404);
+        let post = Request { method: "POST", ..get("/app.js", None) };
+        assert_eq!(serve(&m, &post).status, 405);
+
+        let head = Request { method: "HEAD", ..get("/app.js", None) };
+        let res = serve(&m, &head);
+        assert_eq!((res.status, res.body.len()), (200, 0));
+        assert_eq!(header(&res, "Content-Length"), Some("10"));
+    }
+
+    #[test]
+    fn etags_and_encoding() {
+        let mut m = MemoryAssets::new();
+        let mut asset = Asset::new("/a.css", &b"compressed"[..]).header("Cache-Control", "max-age=60");
+        asset.etag = Some("\"abc\"".into());
+        asset.encoding = Some("gzip");
+        m.insert_asset("/a.css", asset);
+
+        let res = serve(&m, &get("/a.css", Some("bytes=0-1")));
+        assert_eq!(res.status, 200);
+        assert_eq!(header(&res, "Content-Encoding"), Some("gzip"));
+        assert_eq!(header(&res, "Cache-Control"), Some("max-age=60"));
+        assert_eq!(header(&res, "Accept-Ranges"), None);
+
+        let req = Request { if_none_match: Some("\"x\", \"abc\""), ..get("/a.css", None) };
+        let res = serve(&m, &req);
+        assert_eq!((res.status, res.body.len()), (304, 0));
+        assert_eq!(header(&res, "ETag"), Some("\"abc\""));
+    }
+
+    #[test]
+    fn encoding_negotiation() {
+        let mut m = MemoryAssets::new();
+        let mut asset = Asset::new("/a.js", &b"brotli"[..]);
+        asset.encoding = Some("br");
+        asset.identity = Some(Cow::Borrowed(&b"plain"[..]));
+        m.insert_asset("/a.js", asset);
+
+        let accepting = |accept_encoding| serve(&m, &Request { accept_encoding, ..get("/a.js", None) });
+        for accept in &[None, Some("gzip, deflate, br"), Some("*"), Some("BR;q=0.5")] {
+            let res = accepting(*accept);
+            assert_eq!((&*res.body, header(&res, "Content-Encoding")), (&b"brotli"[..], Some("br")), "{:?}", accept);
+            assert_eq!(header(&res, "Vary"), Some("Accept-Encoding"));
+        }
+        // MSHTML.
+        for accept in &[Some("gzip, deflate"), Some("br;q=0, *"), Some("")] {
+            let res = accepting(*accept);
+            assert_eq!((&*res.body, header(&res, "Content-Encoding")), (&b"plain"[..], None), "{:?}", accept);
+        }
+        let res = serve(&m, &Request { accept_encoding: Some("gzip"), ..get("/a.js", Some("bytes=1-2")) });
+        assert_eq!((res.status, &*res.body), (206, &b"la"[..]));
+    }
+
+    #[test]
+    fn memory_index() {
+        let m = memory();
+        assert_eq!(&*m.resolve("/").unwrap().data, b"<html></html>");
+        assert!(m.resolve("/sub/").is_none());
+    }
+
+    #[test]
+    fn directory() {
+        let base = std::env::temp_dir().join(format!("webviewx-assets-{}", std::process::id()));
+        let root = base.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("index.html"), "index").unwrap();
+        fs::write(root.join("sub").join("index.html"), "sub").unwrap();
+        fs::write(root.join("sub").join("a.js"), "a").unwrap();
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+
+        let dir = Directory::new(&root);
+        assert_eq!(&*dir.resolve("/").unwrap().data, b"index");
+        assert_eq!(&*dir.resolve("/sub").unwrap().data, b"sub");
+        let a = dir.resolve("/sub/./a.js").unwrap();
+        assert_eq!((&*a.data, &*a.mime_type), (&b"a"[..], "text/javascript; charset=utf-8"));
+        assert!(dir.resolve("/missing").is_none());
+
+        for path in &["/../secret.txt", "/sub/../../secret.txt", "/..\\secret.txt", "/sub\\..\\..\\secret.txt", "/C:/secret.txt", "/a\0b"] {
+            assert!(dir.resolve(path).is_none(), "{}", path);
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn layered() {
+        let mut over = MemoryAssets::new();
+        over.insert("/app.js", &b"override"[..]);
+        let l = Layered::new().layer(over).layer(memory());
+        assert_eq!(&*l.resolve("/app.js").unwrap().data, b"override");
+        assert_eq!(&*l.resolve("/index.html").unwrap().data, b"<html></html>");
+        assert!(l.resolve("/none").is_none());
+    }
+
+    #[test]
+    fn spa_fallback() {
+        let spa = SpaFallback::new(memory());
+        assert_eq!(&*spa.resolve("/settings/profile").unwrap().data, b"<html></html>");
+        assert_eq!(&*spa.resolve("/app.js").unwrap().data, b"0123456789");
+        assert!(spa.resolve("/missing.js").is_none());
+
+        let spa = SpaFallback::new(memory()).index("/app.js");
+        assert_eq!(&*spa.resolve("/route").unwrap().data, b"0123456789");
+    }
+}
@@ -0,0 +1,236 @@
+//! JavaScript bridge shared by both engines.
+//!
+//! The page talks to the host through `window.webviewx`, which posts messages over
+//! `chrome.webview.postMessage` (WebView2) or `external.invoke` (MSHTML). Messages
+//! starting with [`PREFIX`] are commands for the host and never reach the invoke handler.
+//! The top-level document also reports `dom:<url>` and `load` as it loads, which feed the
+//! navigation events.
+//!
+//! `webviewx.retry()` loads the URL of the last failed navigation again, for error pages.
+//!
+//! `webviewx.engine` is `"webview2"` or `"mshtml"`, and `webviewx.documentMode` the MSHTML
+//! document mode (`null` on WebView2).
+//!
+//! The script sticks to ES3 so it also runs in old MSHTML document modes.
+use std::mem;
+use winapi::{shared::minwindef::*, shared::windef::*, um::winuser::*};
+use crate::policy::NavigationPolicy;
+
+pub(crate) const PREFIX: &str = "__webviewx:";
+
+/// Defines `window.webviewx`. For frameless windows it also turns elements with the
+/// `webviewx-drag` class or `data-webviewx-drag` attribute into drag regions and makes the
+/// outer `resize_border` CSS pixels of the page resize the window.
+pub(crate) fn script(frameless: bool, resize_border: i32) -> String {
+    format!(r#"(function () {{
+  if (window.webviewx) return;
+  var P = '{prefix}', FRAMELESS = {frameless}, BORDER = {border};
+  var WV2 = !!(window.chrome && window.chrome.webview);
+  var post = WV2
+    ? function (m) {{ window.chrome.webview.postMessage(m); }}
+    : function (m) {{ window.external.invoke(m); }};
+  function on(t, e, f) {{ t.addEventListener ? t.addEventListener(e, f, false) : t.attachEvent('on' + e, f); }}
+  window.webviewx = {{
+    engine: WV2 ? 'webview2' : 'mshtml',
+    documentMode: WV2 ? null : document.documentMode || null,
+    invoke: function (m) {{ post(String(m)); }},
+    minimize: function () {{ post(P + 'minimize'); }},
+    maximize: function () {{ post(P + 'maximize'); }},
+    close: function () {{ post(P + 'close'); }},
+    drag: function () {{ post(P + 'drag'); }},
+    retry: function () {{ post(P + 'retry'); }}
+  }};
+  if (window.top === window) {{
+    var dom = false, load = false;
+    var ready = function () {{
+      var state = document.readyState;
+      if (!dom && state !== 'loading') {{ dom = true; post(P + 'dom:' + location.href); }}
+      if (!load && state === 'complete') {{ load = true; post(P + 'load'); }}
+    }};
+    ready();
+    on(document, 'readystatechange', ready);
+    if (document.addEventListener) on(document, 'DOMContentLoaded', ready);
+    on(window, 'load', ready);
+  }}
+  if (!FRAMELESS) return;
+  function isDrag(el) {{
+    for (; el && el.getAttribute; el = el.parentNode) {{
+      if (el.getAttribute('data-webviewx-no-drag') != null) return false;
+      if (el.getAttribute('data-webviewx-drag') != null || /(^|\s)webviewx-drag(\s|$)/.test(el.className)) return true;
+    }}
+    return false;
+  }}
+  function edge(e) {{
+    if (!BORDER) return 0;
+    var root = document.documentElement;
+    var w = root.clientWidth || document.body.clientWidth, h = root.clientHeight || document.body.clientHeight;
+    var l = e.clientX < BORDER, r = e.clientX >= w - BORDER, t = e.clientY < BORDER, b = e.clientY >= h - BORDER;
+    return t ? (l ? 13 : r ? 14 : 12) : b ? (l ? 16 : r ? 17 : 15) : l ? 10 : r ? 11 : 0;
+  }}
+  var CURSORS = {{ 10: 'w-resize', 11: 'e-resize', 12: 'n-resize', 13: 'nw-resize', 14: 'ne-resize', 15: 's-resize', 16: 'sw-resize', 17: 'se-resize' }};
+  var cursor = '';
+  on(document, 'mousemove', function (e) {{
+    var c = CURSORS[edge(e || window.event)] || '';
+    if (c !== cursor) document.documentElement.style.cursor = cursor = c;
+  }});
+  on(document, 'mousedown', function (e) {{
+    e = e || window.event;
+    if (!(e.which ? e.which === 1 : e.button === 1)) return;
+    var hit = edge(e);
+    if (hit) {{
+      post(P + 'resize:' + hit);
+    }} else if (isDrag(e.target || e.srcElement)) {{
+      post(P + (e.detail === 2 ? 'maximize' : 'drag'));
+    }} else {{
+      return;
+    }}
+    e.preventDefault ? e.preventDefault() : (e.returnValue = false);
+  }});
+}})();"#, prefix = PREFIX, frameless = frameless, border = resize_border)
+}
+
+/// Routes link clicks and `window.open` through the host, so MSHTML can apply a
+/// navigation policy. Posts `navigate:<url>` and `open:<url>`.
+pub(crate) fn policy_script() -> String {
+    format!(r#"(function () {{
+  if (window.__webviewx_policy) return;
+  window.__webviewx_policy = true;
+  var P = '{prefix}';
+  function post(m) {{ window.external.invoke(m); }}
+  function absolute(u) {{ var a = document.createElement('a'); a.href = u; return a.href; }}
+  window.open = function (url) {{ if (url) post(P + 'open:' + absolute(url)); return null; }};
+  var click = function (e) {{
+    e = e || window.event;
+    var el = e.target || e.srcElement;
+    while (el && el.nodeName !== 'A') el = el.parentNode;
+    if (!el || !el.href || /^javascript:/i.test(el.href)) return;
+    var here = location.href.split('#')[0];
+    if (el.href.split('#')[0] === here && el.href.indexOf('#') >= 0) return;
+    post(P + (el.target && el.target !== '_self' ? 'open:' : 'navigate:') + el.href);
+    e.preventDefault ? e.preventDefault() : (e.returnValue = false);
+  }};
+  document.addEventListener ? document.addEventListener('click', click, false) : document.attachEvent('onclick', click);
+}})();"#, prefix = PREFIX)
+}
+
+/// Quotes `s` as a JavaScript string literal that is also safe inside `<script>`.
+pub(crate) fn js_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '<' => out.push_str("\\u003c"),
+            '\u{2028}' => out.push_str("\\u2028"),
+            '\u{2029}' => out.push_str("\\u2029"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Name of the command in `msg`, `"invoke"` for the application's messages.
+pub(crate) fn command(msg: &str) -> &str {
+    match msg.strip_prefix(PREFIX) {
+        Some(cmd) => cmd.split(':').next().unwrap_or(cmd),
+        None => "invoke"
+    }
+}
+
+/// Whether `posted`, the URL of a `dom:` report, names `actual`, the document the browser
+/// shows. Anything on the page can post the report, so it only counts when they agree;
+/// the fragment is left to the page.
+pub(crate) fn same_document(posted: &str, actual: &str) -> bool {
+    let normalize = |url: &str| {
+        let url = url.split('#').next().unwrap_or("");
+        let url = urlencoding::decode(url).map_or_else(|_| url.to_owned(), |u| u.into_owned());
+        url.trim_end_matches('/').to_owned()
+    };
+    normalize(posted) == normalize(actual)
+}
+
+/// Whether the page at `url` may run `cmd`. Commands that move, resize or close the window
+/// only run in frameless windows, which pass the pages they trust, and only for those pages.
+fn allowed(cmd: &str, url: &str, trusted: Option<&NavigationPolicy>) -> bool {
+    let window = matches!(cmd, "minimize" | "maximize" | "close" | "drag") || cmd.starts_with("resize:");
+    !window || trusted.is_some_and(|t| t.is_allowed(url))
+}
+
+/// Runs a bridge command sent by the page at `url` on `hwnd`. Returns `false` if `msg` is
+/// not a command.
+pub(crate) fn handle(hwnd: HWND, msg: &str, url: &str, trusted: Option<&NavigationPolicy>) -> bool {
+    let cmd = match msg.strip_prefix(PREFIX) {
+        Some(cmd) => cmd,
+        None => return false
+    };
+    if !allowed(cmd, url, trusted) {
+        log::warn!("ignoring the {:?} command of {}", command(msg), url);
+        return true
+    }
+    unsafe {
+        match cmd {
+            "minimize" => {
+                ShowWindow(hwnd, SW_MINIMIZE);
+            }
+            "maximize" => {
+                ShowWindow(hwnd, if IsZoomed(hwnd) != 0 { SW_RESTORE } else { SW_MAXIMIZE });
+            }
+            "close" => {
+                PostMessageW(hwnd, WM_CLOSE, 0, 0);
+            }
+            "drag" => begin_nc_drag(hwnd, HTCAPTION),
+            _ => {
+                if let Some(Ok(hit)) = cmd.strip_prefix("resize:").map(str::parse::<LRESULT>) {
+                    if (HTLEFT..=HTBOTTOMRIGHT).contains(&hit) {
+                        begin_nc_drag(hwnd, hit);
+                    }
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Starts the system move or size loop as if the user pressed the mouse on `hit`.
+fn begin_nc_drag(hwnd: HWND, hit: LRESULT) {
+    unsafe {
+        let mut pt: POINT = mem::zeroed();
+        GetCursorPos(&mut pt);
+        ReleaseCapture();
+        let pos = MAKELONG(pt.x as WORD, pt.y as WORD);
+        PostMessageW(hwnd, WM_NCLBUTTONDOWN, hit as WPARAM, pos as LPARAM);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_commands_need_a_trusted_page() {
+        let trusted = NavigationPolicy::new().allow("webviewx://app").allow("about:blank");
+        assert!(allowed("drag", "webviewx://app/index.html", Some(&trusted)));
+        assert!(allowed("resize:13", "about:blank", Some(&trusted)));
+        assert!(!allowed("close", "https://example.com/", Some(&trusted)));
+        assert!(!allowed("minimize", "webviewx://app.example.com/", Some(&trusted)));
+        // Not a frameless window.
+        assert!(!allowed("maximize", "webviewx://app/index.html", None));
+        // Other commands are not restricted.
+        assert!(allowed("retry", "https://example.com/", None));
+    }
+
+    #[test]
+    fn forged_document_reports() {
+        let actual = "http://127.0.0.1:50001/a%20b.html";
+        assert!(same_document("http://127.0.0.1:50001/a b.html#top", actual));
+        assert!(same_document("http://127.0.0.1:50001/", "http://127.0.0.1:50001"));
+        // A page claiming to be the application's.
+        assert!(!same_document("webviewx://app/", "https://example.com/"));
+        assert!(!same_document("http://127.0.0.1:50001/a b.html", "https://example.com/#http://127.0.0.1:50001/a b.html"));
+    }
+}
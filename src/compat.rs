@@ -0,0 +1,335 @@
+//! Compatibility layer for the legacy MSHTML engine, enabled with
+//! [`WebViewBuilder::legacy_compat()`](../struct.WebViewBuilder.html#method.legacy_compat).
+//!
+//! MSHTML renders pages without a doctype in quirks mode, and even its newest document mode
+//! lacks most of what modern pages expect. The layer asks for the newest mode, with the
+//! `X-UA-Compatible` meta tag in HTML content and the header of served HTML, and polyfills
+//! `Promise`, `fetch`, `Object.assign` and `CustomEvent`.
+//!
+//! `fetch` goes through the bridge: requests for the application's assets are answered
+//! from the resolver, anything else is sent with `XMLHttpRequest`.
+use std::borrow::Cow;
+use std::sync::Arc;
+use serde_json::{json, Value};
+use crate::assets::{self, AssetResolver};
+use crate::bridge;
+
+pub(crate) const META: &str = r#"<meta http-equiv="X-UA-Compatible" content="IE=edge">"#;
+
+/// The polyfills. Each one only installs itself if the feature is missing, so the script
+/// can run more than once per document.
+pub(crate) fn polyfills() -> String {
+    format!(r#"(function () {{
+  var P = '{prefix}';
+  var hasOwn = function (o, k) {{ return Object.prototype.hasOwnProperty.call(o, k); }};
+  if (!Object.assign) Object.assign = function (target) {{
+    if (target == null) throw new TypeError('Cannot convert undefined or null to object');
+    var to = Object(target);
+    for (var i = 1; i < arguments.length; i++) {{
+      var src = arguments[i];
+      if (src != null) for (var k in src) if (hasOwn(src, k)) to[k] = src[k];
+    }}
+    return to;
+  }};
+  if (typeof window.CustomEvent !== 'function' && document.createEvent) {{
+    var CustomEvent = function (type, params) {{
+      params = params || {{}};
+      var e = document.createEvent('CustomEvent');
+      e.initCustomEvent(type, !!params.bubbles, !!params.cancelable, params.detail);
+      return e;
+    }};
+    if (window.Event) CustomEvent.prototype = window.Event.prototype;
+    window.CustomEvent = CustomEvent;
+  }}
+  if (!window.Promise) window.Promise = (function () {{
+    var defer = window.setImmediate
+      ? function (f) {{ window.setImmediate(f); }}
+      : function (f) {{ setTimeout(f, 0); }};
+    function Promise(executor) {{
+      if (typeof executor !== 'function') throw new TypeError('Promise resolver is not a function');
+      var self = this, done = false;
+      self._state = 0;
+      self._handlers = [];
+      try {{
+        executor(
+          function (v) {{ if (!done) {{ done = true; adopt(self, v); }} }},
+          function (r) {{ if (!done) {{ done = true; settle(self, 2, r); }} }}
+        );
+      }} catch (e) {{
+        if (!done) {{ done = true; settle(self, 2, e); }}
+      }}
+    }}
+    function settle(p, state, value) {{
+      if (p._state) return;
+      p._state = state;
+      p._value = value;
+      for (var i = 0; i < p._handlers.length; i++) run(p, p._handlers[i]);
+      p._handlers = null;
+    }}
+    function adopt(p, v) {{
+      if (v === p) return settle(p, 2, new TypeError('A promise cannot be resolved with itself'));
+      if (v && (typeof v === 'object' || typeof v === 'function')) {{
+        var then, called = false;
+        try {{ then = v.then; }} catch (e) {{ return settle(p, 2, e); }}
+        if (typeof then === 'function') {{
+          try {{
+            then.call(v,
+              function (x) {{ if (!called) {{ called = true; adopt(p, x); }} }},
+              function (r) {{ if (!called) {{ called = true; settle(p, 2, r); }} }});
+          }} catch (e) {{
+            if (!called) {{ called = true; settle(p, 2, e); }}
+          }}
+          return;
+        }}
+      }}
+      settle(p, 1, v);
+    }}
+    function run(p, h) {{
+      defer(function () {{
+        var f = p._state === 1 ? h.fulfilled : h.rejected;
+        if (typeof f !== 'function') return settle(h.promise, p._state, p._value);
+        var r;
+        try {{ r = f(p._value); }} catch (e) {{ return settle(h.promise, 2, e); }}
+        adopt(h.promise, r);
+      }});
+    }}
+    Promise.prototype.then = function (fulfilled, rejected) {{
+      var h = {{ fulfilled: fulfilled, rejected: rejected, promise: new Promise(function () {{}}) }};
+      if (this._state) run(this, h);
+      else this._handlers.push(h);
+      return h.promise;
+    }};
+    Promise.prototype['catch'] = function (rejected) {{ return this.then(null, rejected); }};
+    Promise.prototype['finally'] = function (f) {{
+      return this.then(
+        function (v) {{ return Promise.resolve(f()).then(function () {{ return v; }}); }},
+        function (r) {{ return Promise.resolve(f()).then(function () {{ throw r; }}); }});
+    }};
+    Promise.resolve = function (v) {{
+      return v instanceof Promise ? v : new Promise(function (resolve) {{ resolve(v); }});
+    }};
+    Promise.reject = function (r) {{ return new Promise(function (resolve, reject) {{ reject(r); }}); }};
+    Promise.all = function (items) {{
+      return new Promise(function (resolve, reject) {{
+        var out = [], left = items.length;
+        if (!left) return resolve(out);
+        for (var i = 0; i < items.length; i++) (function (i) {{
+          Promise.resolve(items[i]).then(function (v) {{ out[i] = v; if (!--left) resolve(out); }}, reject);
+        }})(i);
+      }});
+    }};
+    Promise.race = function (items) {{
+      return new Promise(function (resolve, reject) {{
+        for (var i = 0; i < items.length; i++) Promise.resolve(items[i]).then(resolve, reject);
+      }});
+    }};
+    return Promise;
+  }})();
+  if (!window.fetch && window.JSON) {{
+    var Headers = function (init) {{
+      this._map = {{}};
+      var source = init instanceof Headers ? init._map : init || {{}};
+      if (source.length !== undefined) {{
+        for (var i = 0; i < source.length; i++) this.append(source[i][0], source[i][1]);
+      }} else {{
+        for (var k in source) if (hasOwn(source, k)) this.append(k, source[k]);
+      }}
+    }};
+    Headers.prototype.append = function (name, value) {{
+      name = String(name).toLowerCase();
+      this._map[name] = hasOwn(this._map, name) ? this._map[name] + ', ' + value : String(value);
+    }};
+    Headers.prototype.set = function (name, value) {{ this._map[String(name).toLowerCase()] = String(value); }};
+    Headers.prototype.get = function (name) {{
+      name = String(name).toLowerCase();
+      return hasOwn(this._map, name) ? this._map[name] : null;
+    }};
+    Headers.prototype.has = function (name) {{ return hasOwn(this._map, String(name).toLowerCase()); }};
+    Headers.prototype['delete'] = function (name) {{ delete this._map[String(name).toLowerCase()]; }};
+    Headers.prototype.forEach = function (f, self) {{
+      for (var k in this._map) if (hasOwn(this._map, k)) f.call(self, this._map[k], k, this);
+    }};
+    var Response = function (body, init) {{
+      init = init || {{}};
+      this.status = init.status === undefined ? 200 : init.status;
+      this.statusText = init.statusText || '';
+      this.ok = this.status >= 200 && this.status < 300;
+      this.headers = new Headers(init.headers);
+      this.url = init.url || '';
+      this._body = body == null ? '' : String(body);
+    }};
+    Response.prototype.text = function () {{ return window.Promise.resolve(this._body); }};
+    Response.prototype.json = function () {{
+      var body = this._body;
+      return new window.Promise(function (resolve) {{ resolve(JSON.parse(body)); }});
+    }};
+    var absolute = function (url) {{ var a = document.createElement('a'); a.href = url; return a.href; }};
+    var xhr = function (req, resolve, reject) {{
+      var x = new XMLHttpRequest();
+      x.open(req.method, req.url, true);
+      for (var k in req.headers) if (hasOwn(req.headers, k)) x.setRequestHeader(k, req.headers[k]);
+      x.onreadystatechange = function () {{
+        if (x.readyState !== 4) return;
+        if (!x.status) return reject(new TypeError('Failed to fetch'));
+        var headers = [], lines = x.getAllResponseHeaders().split(/\r?\n/);
+        for (var i = 0; i < lines.length; i++) {{
+          var at = lines[i].indexOf(':');
+          if (at > 0) headers.push([lines[i].slice(0, at), lines[i].slice(at + 1).replace(/^\s+/, '')]);
+        }}
+        // MSHTML reports 204 as 1223.
+        resolve(new Response(x.responseText, {{
+          status: x.status === 1223 ? 204 : x.status,
+          statusText: x.statusText,
+          headers: headers,
+          url: req.url
+        }}));
+      }};
+      x.send(req.body);
+    }};
+    var pending = {{}}, next = 0;
+    window.__webviewx_fetched = function (id, res) {{
+      var p = pending[id];
+      delete pending[id];
+      if (!p) return;
+      if (res.network) xhr(p.req, p.resolve, p.reject);
+      else p.resolve(new Response(res.body, res));
+    }};
+    window.fetch = function (input, init) {{
+      init = init || {{}};
+      var headers = new Headers(init.headers || input.headers);
+      var req = {{
+        url: absolute(typeof input === 'string' ? input : input.url),
+        method: String(init.method || input.method || 'GET').toUpperCase(),
+        headers: headers._map,
+        body: init.body == null ? null : String(init.body)
+      }};
+      return new window.Promise(function (resolve, reject) {{
+        var id = ++next;
+        pending[id] = {{ req: req, resolve: resolve, reject: reject }};
+        window.external.invoke(P + 'fetch:' + id + ':' + JSON.stringify(req));
+      }});
+    }};
+    window.Headers = Headers;
+    window.Response = Response;
+  }}
+}})();"#, prefix = bridge::PREFIX)
+}
+
+/// Wraps the application's assets so served HTML asks for the newest document mode and gets
+/// the polyfills before its own scripts run. Compressed HTML is edited through its identity
+/// copy, which bundles of [`embed`](../embed/index.html) always carry.
+pub(crate) fn assets(resolver: Arc<dyn AssetResolver>) -> Arc<dyn AssetResolver> {
+    let head = format!("{}<script>{}</script>", META, polyfills());
+    Arc::new(move |path: &str| {
+        let mut asset = resolver.resolve(path)?;
+        if asset.mime_type.starts_with("text/html") {
+            let plain = match (asset.encoding, &asset.identity) {
+                (None, _) => Some(&asset.data),
+                (Some(_), identity) => identity.as_ref()
+            };
+            match plain.map(|data| std::str::from_utf8(data)) {
+                Some(Ok(html)) => {
+                    asset.data = Cow::Owned(crate::legacy::insert_into_head(html, &head).into_bytes());
+                    asset.encoding = None;
+                    asset.identity = None;
+                }
+                _ => log::warn!("{}: compressed or not UTF-8, served without the compatibility layer", path)
+            }
+            asset.headers.push(("X-UA-Compatible".into(), "IE=edge".into()));
+        }
+        Some(asset)
+    })
+}
+
+/// Answers a `fetch:<id>:<request>` message of the `fetch` polyfill. `origins` are the URL
+/// prefixes of the application's assets; other URLs are left to the network.
+pub(crate) fn fetch(resolver: Option<&dyn AssetResolver>, origins: &[String], msg: &str) -> Option<String> {
+    let (id, request) = msg.split_once(':')?;
+    let id: u64 = id.parse().ok()?;
+    let request: Value = serde_json::from_str(request).ok()?;
+    let url = request["url"].as_str()?;
+    let origin = origins.iter().find(|origin| {
+        url.strip_prefix(origin.as_str()).is_some_and(|rest| rest.is_empty() || origin.ends_with('/') || rest.starts_with(['/', '?', '#']))
+    });
+    let reply = match (resolver, origin) {
+        (Some(resolver), Some(origin)) => {
+            let res = assets::serve(resolver, &assets::Request {
+                method: request["method"].as_str().unwrap_or("GET"),
+                path: &assets::request_path(url, origin),
+                range: request["headers"]["range"].as_str(),
+                if_none_match: None,
+                accept_encoding: Some("identity"),
+            });
+            if res.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Content-Encoding")) {
+                // No identity copy: the loopback server hands it to the engine, which decodes it.
+                json!({ "network": true })
+            } else {
+                let headers: Vec<_> = res.headers.iter().map(|(name, value)| json!([name, value])).collect();
+                json!({
+                    "status": res.status,
+                    "statusText": res.reason,
+                    "headers": headers,
+                    "url": url,
+                    "body": String::from_utf8_lossy(&res.body),
+                })
+            }
+        }
+        _ => json!({ "network": true })
+    };
+    // JSON allows the line separators JavaScript string literals do not.
+    let reply = reply.to_string().replace('\u{2028}', "\\u2028").replace('\u{2029}', "\\u2029");
+    Some(format!("window.__webviewx_fetched({}, {});", id, reply))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{Asset, MemoryAssets};
+
+    fn reply(js: &str) -> Value {
+        let json = js.strip_prefix("window.__webviewx_fetched(7, ").unwrap().strip_suffix(");").unwrap();
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn fetch_assets() {
+        let mut assets = MemoryAssets::new();
+        assets.insert("/data.json", &b"{\"a\":1}"[..]);
+        let origins = ["app://".to_owned(), "http://127.0.0.1:8080".to_owned()];
+        let fetch = |url: &str| {
+            let msg = format!("7:{}", json!({ "url": url, "method": "GET", "headers": {}, "body": null }));
+            reply(&fetch(Some(&assets), &origins, &msg).unwrap())
+        };
+        let res = fetch("app://data.json");
+        assert_eq!((res["status"].as_u64(), res["body"].as_str()), (Some(200), Some("{\"a\":1}")));
+        assert_eq!(fetch("http://127.0.0.1:8080/data.json?x=1")["status"], 200);
+        assert_eq!(fetch("app://missing")["status"], 404);
+        assert_eq!(fetch("http://127.0.0.1:80801/data.json")["network"], true);
+        assert_eq!(fetch("https://example.com/")["network"], true);
+    }
+
+    #[test]
+    fn served_html() {
+        let mut assets = MemoryAssets::new();
+        assets.insert("/index.html", &b"<!doctype html><head><title>x</title>"[..]);
+        assets.insert("/app.js", &b"x()"[..]);
+        let assets = super::assets(Arc::new(assets));
+        let page = assets.resolve("/index.html").unwrap();
+        assert!(std::str::from_utf8(&page.data).unwrap().starts_with(&format!("<!doctype html><head>{}<script>", META)));
+        assert!(page.headers.iter().any(|(n, v)| n == "X-UA-Compatible" && v == "IE=edge"));
+        assert_eq!(&*assets.resolve("/app.js").unwrap().data, b"x()");
+    }
+
+    #[test]
+    fn served_compressed_html() {
+        let mut assets = MemoryAssets::new();
+        let mut page = Asset::new("/index.html", &b"gzip"[..]);
+        page.encoding = Some("gzip");
+        page.identity = Some(Cow::Borrowed(&b"<head><title>x</title>"[..]));
+        assets.insert_asset("/index.html", page);
+        let assets = super::assets(Arc::new(assets));
+        let page = assets.resolve("/index.html").unwrap();
+        assert!(std::str::from_utf8(&page.data).unwrap().starts_with(&format!("<head>{}<script>", META)));
+        assert_eq!((page.encoding, page.identity), (None, None));
+    }
+}
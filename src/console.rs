@@ -0,0 +1,213 @@
+//! Console output and uncaught errors of the page.
+//!
+//! When capture is enabled, a document script wraps the `console` methods and listens for
+//! `error` and `unhandledrejection` events. Every record is posted to the host as
+//! `console:<json>`, passed to the application's callback and logged through the `log`
+//! crate under the `webview::console` target.
+//!
+//! The WebView2 SDK we bind does not expose the DevTools protocol, so both engines use the
+//! script. On MSHTML it only runs once the document does, see
+//! [`init_script()`](../struct.WebViewBuilder.html#method.init_script); output of the very
+//! first scripts of a page loaded from a URL is missed there.
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use serde_json::Value;
+use crate::bridge;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConsoleLevel {
+    /// `console.debug` and `console.trace`.
+    Debug,
+    /// `console.log` and `console.info`.
+    Info,
+    Warn,
+    /// `console.error`, uncaught exceptions and unhandled promise rejections.
+    Error,
+}
+
+impl ConsoleLevel {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "debug" => ConsoleLevel::Debug,
+            "info" => ConsoleLevel::Info,
+            "warn" => ConsoleLevel::Warn,
+            "error" => ConsoleLevel::Error,
+            _ => return None
+        })
+    }
+
+    fn log_level(self) -> log::Level {
+        match self {
+            ConsoleLevel::Debug => log::Level::Debug,
+            ConsoleLevel::Info => log::Level::Info,
+            ConsoleLevel::Warn => log::Level::Warn,
+            ConsoleLevel::Error => log::Level::Error,
+        }
+    }
+}
+
+/// Passed to [`on_console_message`](../struct.WebViewBuilder.html#method.on_console_message).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsoleMessage {
+    pub level: ConsoleLevel,
+    /// The arguments of a console call joined by spaces, or the error message.
+    pub message: String,
+    /// URL of the script, where the engine tells.
+    pub source: Option<String>,
+    pub line: Option<u32>,
+    /// Stack of the error, where the engine provides stacks.
+    pub stack: Option<String>,
+}
+
+impl Display for ConsoleMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        match (&self.source, self.line) {
+            (Some(source), Some(line)) => write!(f, " ({}:{})", source, line),
+            (Some(source), None) => write!(f, " ({})", source),
+            _ => Ok(())
+        }
+    }
+}
+
+pub type ConsoleHandler = Box<dyn FnMut(&ConsoleMessage)>;
+
+/// Delivers the records posted by [`script()`].
+pub(crate) struct Console {
+    handler: Option<ConsoleHandler>,
+}
+
+impl Console {
+    pub(crate) fn new(handler: Option<ConsoleHandler>) -> Self {
+        Console { handler }
+    }
+
+    pub(crate) fn into_handler(self) -> Option<ConsoleHandler> {
+        self.handler
+    }
+
+    /// Handles the JSON of a `console:` message.
+    pub(crate) fn received(&mut self, json: &str) {
+        let message = match parse(json) {
+            Some(message) => message,
+            None => return
+        };
+        log::log!(target: "webview::console", message.level.log_level(), "{}", message);
+        if let Some(f) = &mut self.handler {
+            f(&message);
+        }
+    }
+}
+
+fn parse(json: &str) -> Option<ConsoleMessage> {
+    let record: Value = serde_json::from_str(json).ok()?;
+    let field = |name: &str| record.get(name).and_then(Value::as_str).map(str::to_owned);
+    Some(ConsoleMessage {
+        level: ConsoleLevel::parse(record.get("level")?.as_str()?)?,
+        message: field("message").unwrap_or_default(),
+        source: field("source").filter(|s| !s.is_empty()),
+        line: record.get("line").and_then(Value::as_u64).and_then(|l| u32::try_from(l).ok()).filter(|l| *l > 0),
+        stack: field("stack").filter(|s| !s.is_empty()),
+    })
+}
+
+/// Document script capturing console output and uncaught errors. ES3, and it builds the
+/// JSON itself, as old MSHTML document modes have neither `JSON` nor `console`.
+pub(crate) fn script() -> String {
+    format!(r#"(function () {{
+  if (window.__webviewx_console) return;
+  window.__webviewx_console = true;
+  var P = '{prefix}';
+  var post = window.chrome && window.chrome.webview
+    ? function (m) {{ window.chrome.webview.postMessage(m); }}
+    : function (m) {{ window.external.invoke(m); }};
+  function quote(s) {{
+    return '"' + String(s).replace(/[\\"\u0000-\u001f\u2028\u2029]/g, function (c) {{
+      return '\\u' + ('000' + c.charCodeAt(0).toString(16)).slice(-4);
+    }}) + '"';
+  }}
+  function describe(v) {{
+    if (typeof v === 'string') return v;
+    if (v && v.message !== undefined && v.name !== undefined) return v.name + ': ' + v.message;
+    try {{
+      if (window.JSON && v && typeof v === 'object') return JSON.stringify(v);
+    }} catch (e) {{}}
+    return String(v);
+  }}
+  function send(level, message, source, line, stack) {{
+    try {{
+      post(P + 'console:{{"level":' + quote(level) + ',"message":' + quote(message)
+        + ',"source":' + (source ? quote(source) : 'null') + ',"line":' + (line > 0 ? Number(line) : 'null')
+        + ',"stack":' + (stack ? quote(stack) : 'null') + '}}');
+    }} catch (e) {{}}
+  }}
+  // Where the console call came from, from the stack of engines that have one.
+  function caller() {{
+    var lines = String(new Error().stack || '').split('\n');
+    var m = /\(?([^\s()]+):(\d+):\d+\)?\s*$/.exec(lines[3] || '');
+    return m ? {{ source: m[1], line: m[2] }} : {{}};
+  }}
+  var console = window.console = window.console || {{}};
+  var LEVELS = {{ debug: 'debug', trace: 'debug', log: 'info', info: 'info', warn: 'warn', error: 'error' }};
+  function wrap(name) {{
+    var original = console[name];
+    console[name] = function () {{
+      var parts = [], stack = null;
+      for (var i = 0; i < arguments.length; i++) {{
+        parts.push(describe(arguments[i]));
+        if (!stack && arguments[i] && arguments[i].stack) stack = String(arguments[i].stack);
+      }}
+      var at = caller();
+      send(LEVELS[name], parts.join(' '), at.source, at.line, stack);
+      if (original) Function.prototype.apply.call(original, console, arguments);
+    }};
+  }}
+  for (var name in LEVELS) if (LEVELS.hasOwnProperty(name)) wrap(name);
+  if (window.addEventListener) {{
+    window.addEventListener('error', function (e) {{
+      // Failed resources fire `error` on their element, not with a message.
+      if (e.message === undefined) return;
+      send('error', e.message, e.filename, e.lineno, e.error && e.error.stack);
+    }}, false);
+    window.addEventListener('unhandledrejection', function (e) {{
+      var r = e.reason;
+      send('error', 'Uncaught (in promise) ' + describe(r), null, 0, r && r.stack);
+    }}, false);
+  }} else {{
+    var previous = window.onerror;
+    window.onerror = function (message, source, line) {{
+      send('error', message, source, line, null);
+      return previous ? previous.apply(this, arguments) : false;
+    }};
+  }}
+}})();"#, prefix = bridge::PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let m = parse(r#"{"level":"error","message":"Uncaught TypeError: x is not a function","source":"https://app.localhost/main.js","line":12,"stack":"TypeError: x\n    at main.js:12:3"}"#).unwrap();
+        assert_eq!(m, ConsoleMessage {
+            level: ConsoleLevel::Error,
+            message: "Uncaught TypeError: x is not a function".to_owned(),
+            source: Some("https://app.localhost/main.js".to_owned()),
+            line: Some(12),
+            stack: Some("TypeError: x\n    at main.js:12:3".to_owned()),
+        });
+        assert_eq!(m.to_string(), "Uncaught TypeError: x is not a function (https://app.localhost/main.js:12)");
+
+        let m = parse(r#"{"level":"info","message":"hello 1","source":null,"line":null,"stack":null}"#).unwrap();
+        assert_eq!(m.to_string(), "hello 1");
+        assert_eq!((m.level, m.source, m.line, m.stack), (ConsoleLevel::Info, None, None, None));
+    }
+
+    #[test]
+    fn malformed_records() {
+        assert!(parse(r#"{"level":"fatal","message":"x"}"#).is_none());
+        assert!(parse(r#"{"message":"x"}"#).is_none());
+        assert!(parse("not json").is_none());
+    }
+}
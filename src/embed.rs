@@ -0,0 +1,283 @@
+//! Build script helper behind the `embed` feature.
+//!
+//! Bakes a frontend directory into the binary. In `build.rs`:
+//!
+//! ```no_run
+//! webview_x::embed::Bundle::new("dist").build().unwrap();
+//! ```
+//!
+//! and in the application:
+//!
+//! ```ignore
+//! let webview = WebViewBuilder::new()
+//!     .assets("app", webview_x::embed_assets!("dist"))
+//!     .url("app://index.html")
+//!     .build()?;
+//! ```
+//!
+//! Text assets are precompressed at build time, so the binary carries no decompressor. Both
+//! engines decode gzip, and gzip files are stored only in compressed form. Brotli is WebView2
+//! only; brotli bundles also carry the uncompressed files, which are served to MSHTML and
+//! other clients that do not accept `br`. HTML always keeps an uncompressed copy for the
+//! MSHTML [compatibility layer](../struct.WebViewBuilder.html#method.legacy_compat).
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::assets::mime_type;
+
+/// Compression applied to text assets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Brotli,
+}
+
+/// A directory to embed, see the [module documentation](index.html).
+pub struct Bundle {
+    name: String,
+    dir: PathBuf,
+    compression: Compression,
+}
+
+impl Bundle {
+    /// Bundle named `name`, read from the directory of the same name relative to the crate root.
+    ///
+    /// The name is what [`embed_assets!`](../macro.embed_assets.html) takes and may only contain
+    /// ASCII letters, digits, `-` and `_`.
+    pub fn new(name: &str) -> Self {
+        Bundle {
+            name: name.to_owned(),
+            dir: PathBuf::from(name),
+            compression: Compression::Gzip,
+        }
+    }
+
+    /// Reads the assets from `dir` instead, relative to the crate root.
+    pub fn dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dir = dir.as_ref().to_owned();
+        self
+    }
+
+    /// Defaults to [`Compression::Gzip`].
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Writes the bundle to `OUT_DIR`. Must be called from a build script.
+    pub fn build(self) -> io::Result<()> {
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid bundle name"))
+        }
+        let out_dir = PathBuf::from(env("OUT_DIR")?);
+        let dir = PathBuf::from(env("CARGO_MANIFEST_DIR")?).join(&self.dir);
+        println!("cargo:rerun-if-changed={}", dir.display());
+
+        let data_dir = out_dir.join(format!("webviewx-assets-{}", self.name));
+        fs::create_dir_all(&data_dir)?;
+        let entries = self.entries(&dir, &data_dir)?;
+        fs::write(out_dir.join(format!("webviewx-assets-{}.rs", self.name)), code(&entries)?)
+    }
+
+    /// Reads the files below `dir`, sorted by path, writing compressed data to `data_dir`.
+    fn entries(&self, dir: &Path, data_dir: &Path) -> io::Result<Vec<Entry>> {
+        let mut files = Vec::new();
+        collect(dir, dir, &mut files)?;
+        files.sort();
+
+        let mut entries = Vec::new();
+        for (i, (path, file)) in files.into_iter().enumerate() {
+            let raw = fs::read(&file)?;
+            let mime = mime_type(&path);
+            let mut entry = Entry {
+                path,
+                mime_type: mime,
+                encoding: None,
+                etag: format!("\"{:016x}\"", fnv1a(&raw)),
+                data: file.clone(),
+                identity: None,
+            };
+            if let Some((name, compressed)) = self.compress(mime, &raw)? {
+                // Only worth a header when it saves at least a tenth.
+                if compressed.len() < raw.len() / 10 * 9 {
+                    entry.data = data_dir.join(format!("{}.{}", i, name));
+                    fs::write(&entry.data, compressed)?;
+                    entry.encoding = Some(name);
+                    if name == "br" || mime.starts_with("text/html") {
+                        entry.identity = Some(file);
+                    }
+                }
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Returns the content encoding and compressed data for compressible types.
+    fn compress(&self, mime: &str, raw: &[u8]) -> io::Result<Option<(&'static str, Vec<u8>)>> {
+        let compressible = mime.starts_with("text/")
+            || ["json", "xml", "wasm", "svg"].iter().any(|t| mime.contains(t));
+        if !compressible {
+            return Ok(None)
+        }
+        Ok(match self.compression {
+            Compression::None => None,
+            Compression::Gzip => {
+                let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                e.write_all(raw)?;
+                Some(("gzip", e.finish()?))
+            }
+            Compression::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut e = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+                    e.write_all(raw)?;
+                }
+                Some(("br", out))
+            }
+        })
+    }
+}
+
+/// A file of the bundle, with the files holding its data.
+struct Entry {
+    path: String,
+    mime_type: &'static str,
+    encoding: Option<&'static str>,
+    etag: String,
+    data: PathBuf,
+    identity: Option<PathBuf>,
+}
+
+/// The `EmbeddedFile` slice [`embed_assets!`](../macro.embed_assets.html) includes.
+fn code(entries: &[Entry]) -> io::Result<String> {
+    let include = |file: &Path| -> io::Result<String> {
+        let file = file.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "non UTF-8 path"))?;
+        Ok(format!("include_bytes!({:?})", file))
+    };
+    let mut code = String::from("&[\n");
+    for e in entries {
+        let identity = match &e.identity {
+            Some(file) => format!("Some({})", include(file)?),
+            None => "None".to_owned()
+        };
+        code.push_str(&format!(
+            "    EmbeddedFile {{ path: {:?}, mime_type: {:?}, encoding: {:?}, etag: {:?}, data: {}, identity: {} }},\n",
+            e.path,
+            e.mime_type,
+            e.encoding,
+            e.etag,
+            include(&e.data)?,
+            identity,
+        ));
+    }
+    code.push(']');
+    Ok(code)
+}
+
+fn env(key: &str) -> io::Result<String> {
+    std::env::var(key).map_err(|_| io::Error::new(io::ErrorKind::NotFound, "not running in a build script"))
+}
+
+/// Collects `(request path, file)` pairs below `dir`.
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(root, &path, files)?;
+            continue
+        }
+        let rel = path.strip_prefix(root).unwrap_or(&path);
+        let mut key = String::new();
+        for part in rel.components() {
+            let part = part.as_os_str().to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "non UTF-8 path"))?;
+            key.push('/');
+            key.push_str(part);
+        }
+        files.push((key, path));
+    }
+    Ok(())
+}
+
+/// 64-bit FNV-1a, stable across builds unlike `DefaultHasher`.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn bundle(test: &str, compression: Compression) -> (PathBuf, Vec<Entry>) {
+        let base = std::env::temp_dir().join(format!("webviewx-embed-{}-{}-{:?}", std::process::id(), test, compression));
+        let (dir, data_dir) = (base.join("dist"), base.join("out"));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(dir.join("app.js"), "console.log('hello');\n".repeat(100)).unwrap();
+        fs::write(dir.join("css").join("a.css"), "a{}").unwrap();
+        fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G'].repeat(100)).unwrap();
+        fs::write(dir.join("index.html"), "<p>hi</p>".repeat(50)).unwrap();
+        let entries = Bundle::new("dist").compression(compression).entries(&dir, &data_dir).unwrap();
+        (base, entries)
+    }
+
+    fn decode(entry: &Entry) -> Vec<u8> {
+        let data = fs::read(&entry.data).unwrap();
+        let mut out = Vec::new();
+        match entry.encoding {
+            Some("gzip") => { flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut out).unwrap(); }
+            Some("br") => { brotli::Decompressor::new(&data[..], 4096).read_to_end(&mut out).unwrap(); }
+            _ => out = data,
+        }
+        out
+    }
+
+    #[test]
+    fn gzip_bundle() {
+        let (base, entries) = bundle("gzip", Compression::Gzip);
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        // Sorted for `EmbeddedAssets`' binary search.
+        assert_eq!(paths, ["/app.js", "/css/a.css", "/index.html", "/logo.png"]);
+        let encodings: Vec<_> = entries.iter().map(|e| e.encoding).collect();
+        // `a.css` does not shrink by a tenth, and PNGs are not compressible.
+        assert_eq!(encodings, [Some("gzip"), None, Some("gzip"), None]);
+        // HTML keeps a copy for the compatibility layer.
+        let identities: Vec<_> = entries.iter().map(|e| e.identity.is_some()).collect();
+        assert_eq!(identities, [false, false, true, false]);
+        for e in &entries {
+            let raw = fs::read(base.join("dist").join(e.path.trim_start_matches('/'))).unwrap();
+            assert_eq!(decode(e), raw, "{}", e.path);
+            assert_eq!(e.etag, format!("\"{:016x}\"", fnv1a(&raw)));
+        }
+        let code = code(&entries).unwrap();
+        assert!(code.contains(r#"path: "/app.js", mime_type: "text/javascript; charset=utf-8", encoding: Some("gzip")"#));
+        assert!(code.contains("identity: None"));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn brotli_bundle_keeps_identity_copies() {
+        let (base, entries) = bundle("brotli", Compression::Brotli);
+        let (gzip_base, gzip) = bundle("brotli", Compression::Gzip);
+        let js = &entries[0];
+        assert_eq!((js.encoding, js.identity.as_deref()), (Some("br"), Some(&*base.join("dist").join("app.js"))));
+        assert_eq!(decode(js), fs::read(js.identity.as_ref().unwrap()).unwrap());
+        assert!(entries[1].identity.is_none());
+        // ETags depend on the content only.
+        let etags = |entries: &[Entry]| entries.iter().map(|e| e.etag.clone()).collect::<Vec<_>>();
+        assert_eq!(etags(&entries), etags(&gzip));
+        assert!(code(&entries).unwrap().contains("identity: Some(include_bytes!("));
+        fs::remove_dir_all(&base).unwrap();
+        fs::remove_dir_all(&gzip_base).unwrap();
+    }
+
+    #[test]
+    fn stable_etags() {
+        // Reference values of 64-bit FNV-1a.
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,415 @@
+//! Settings of the browser process, and an [`Environment`] to share it.
+//!
+//! WebView2 takes all of them; they apply to the browser process, so webviews sharing a user
+//! data directory should use the same settings. MSHTML runs in the application's process
+//! and only takes the [`Proxy`], which then applies to all of its webviews.
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Once;
+use std::time::Duration;
+use std::{fs, thread};
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::minwinbase::STILL_ACTIVE;
+use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess};
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winnt::{PROCESS_QUERY_LIMITED_INFORMATION, SYNCHRONIZE};
+
+/// Proxy used by the webview.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Proxy {
+    /// The system's proxy settings.
+    System,
+    /// Connect directly.
+    Direct,
+    /// Go through `server`, e.g. `proxy:8080` or `http=proxy:8080;https=proxy:8443`, except
+    /// for the hosts in `bypass`, e.g. `<local>` or `*.example.com`.
+    Server { server: String, bypass: Vec<String> },
+    /// Use the proxy auto-config script at this URL. Not supported on MSHTML.
+    Pac(String),
+}
+
+/// Browser process settings of a [`WebViewBuilder`](../struct.WebViewBuilder.html).
+#[derive(Clone, Debug)]
+pub struct BrowserEnvironment {
+    /// Where the browser keeps its profile: cookies, storage and caches. `None` picks
+    /// `%LOCALAPPDATA%\<exe name>\WebView2`, as the runtime's own default next to the
+    /// executable is not writable when installed below `Program Files`.
+    pub user_data_dir: Option<PathBuf>,
+    /// UI language of the browser, e.g. `de-DE`. Also the default `Accept-Language`.
+    pub language: Option<String>,
+    pub proxy: Proxy,
+    /// Additional browser command line arguments.
+    pub arguments: Vec<String>,
+    /// Use a fresh profile in a temporary directory, removed again once the webviews using
+    /// it are gone. Takes precedence over `user_data_dir`.
+    pub ephemeral: bool,
+}
+
+impl Default for BrowserEnvironment {
+    fn default() -> Self {
+        BrowserEnvironment {
+            user_data_dir: None,
+            language: None,
+            proxy: Proxy::System,
+            arguments: Vec::new(),
+            ephemeral: false,
+        }
+    }
+}
+
+impl BrowserEnvironment {
+    pub fn new() -> Self {
+        BrowserEnvironment::default()
+    }
+
+    pub fn user_data_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.user_data_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_owned());
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Adds a browser command line argument, e.g. `--disable-gpu`.
+    pub fn argument(mut self, argument: &str) -> Self {
+        self.arguments.push(argument.to_owned());
+        self
+    }
+
+    pub fn ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    /// Browser command line arguments for the proxy, followed by [`arguments`](#structfield.arguments).
+    pub(crate) fn browser_arguments(&self) -> Vec<String> {
+        let mut args = match &self.proxy {
+            Proxy::System => Vec::new(),
+            Proxy::Direct => vec!["--no-proxy-server".to_owned()],
+            Proxy::Server { server, bypass } if bypass.is_empty() => vec![format!("--proxy-server={}", server)],
+            Proxy::Server { server, bypass } => vec![
+                format!("--proxy-server={}", server),
+                format!("--proxy-bypass-list={}", bypass.join(";")),
+            ],
+            Proxy::Pac(url) => vec![format!("--proxy-pac-url={}", url)],
+        };
+        args.extend(self.arguments.iter().cloned());
+        args
+    }
+
+    /// The user data directory for a webview that is not ephemeral.
+    pub(crate) fn persistent_user_data_dir(&self) -> Option<PathBuf> {
+        self.user_data_dir.clone().or_else(|| {
+            default_user_data_dir(std::env::var_os("LOCALAPPDATA"), std::env::current_exe().ok())
+        })
+    }
+}
+
+fn default_user_data_dir(local_app_data: Option<OsString>, exe: Option<PathBuf>) -> Option<PathBuf> {
+    let name = exe?.file_stem()?.to_owned();
+    Some(PathBuf::from(local_app_data?).join(name).join("WebView2"))
+}
+
+/// A browser environment webviews can share.
+///
+/// Webviews built with the same `Environment`, see
+/// [`shared_environment()`](../struct.WebViewBuilder.html#method.shared_environment), share
+/// one WebView2 browser process and its profile, so also cookies and storage. The browser
+/// process starts with the first of them and ends once the last one is closed and every
+/// handle is dropped. An ephemeral profile is removed then too.
+///
+/// Handles are cheap to clone, and only usable on the thread that created them.
+#[derive(Clone)]
+pub struct Environment {
+    shared: Rc<Shared>,
+}
+
+struct Shared {
+    settings: BrowserEnvironment,
+    user_data_dir: Option<PathBuf>,
+    state: RefCell<State>,
+    /// Incremented for every environment created, so a webview only discards the one its
+    /// browser process failed in.
+    generation: Cell<u64>,
+    profile: Option<TempProfile>,
+}
+
+type Waiter = Box<dyn FnOnce(Result<(webview2::Environment, u64), String>)>;
+
+enum State {
+    Idle,
+    /// Webviews waiting for the environment being created.
+    Creating(Vec<Waiter>),
+    Ready(webview2::Environment),
+}
+
+impl Environment {
+    /// Nothing is started until the first webview needs the environment.
+    pub fn new(settings: BrowserEnvironment) -> Self {
+        let profile = if settings.ephemeral { Some(TempProfile::new()) } else { None };
+        let user_data_dir = match &profile {
+            Some(profile) => Some(profile.path().to_owned()),
+            None => settings.persistent_user_data_dir()
+        };
+        Environment {
+            shared: Rc::new(Shared {
+                settings,
+                user_data_dir,
+                state: RefCell::new(State::Idle),
+                generation: Cell::new(0),
+                profile,
+            }),
+        }
+    }
+
+    pub fn settings(&self) -> &BrowserEnvironment {
+        &self.shared.settings
+    }
+
+    /// Calls `f` with the WebView2 environment and its generation, creating the environment
+    /// first if there is none. `f` runs from the message loop unless the environment exists.
+    pub(crate) fn get<F: FnOnce(Result<(webview2::Environment, u64), String>) + 'static>(&self, f: F) {
+        let mut state = self.shared.state.borrow_mut();
+        match &mut *state {
+            State::Ready(env) => {
+                let env = env.clone();
+                drop(state);
+                return f(Ok((env, self.shared.generation.get())))
+            }
+            State::Creating(waiters) => return waiters.push(Box::new(f)),
+            State::Idle => *state = State::Creating(vec![Box::new(f)]),
+        }
+        drop(state);
+
+        let settings = &self.shared.settings;
+        let mut builder = webview2::Environment::builder();
+        if let Some(dir) = &self.shared.user_data_dir {
+            builder = builder.with_user_data_folder(dir);
+        }
+        if let Some(language) = &settings.language {
+            builder = builder.with_language(language);
+        }
+        let arguments = settings.browser_arguments().join(" ");
+        if !arguments.is_empty() {
+            builder = builder.with_additional_browser_arguments(&arguments);
+        }
+        let shared = Rc::downgrade(&self.shared);
+        let r = builder.build(move |env| {
+            if let Some(shared) = shared.upgrade() {
+                shared.created(env.map_err(|e| format!("creating the environment failed: {}", e)));
+            }
+            Ok(())
+        });
+        if let Err(e) = r {
+            self.shared.created(Err(format!("creating the environment failed: {}", e)));
+        }
+    }
+
+    /// Forgets the environment of `generation` after its browser process exited. The next
+    /// [`get()`](#method.get) creates a new one.
+    pub(crate) fn discard(&self, generation: u64) {
+        let mut state = self.shared.state.borrow_mut();
+        if matches!(*state, State::Ready(_)) && self.shared.generation.get() == generation {
+            *state = State::Idle;
+        }
+    }
+
+    /// Remembers the browser process, which an ephemeral profile waits for before removal.
+    pub(crate) fn browser_started(&self, pid: u32) {
+        if let Some(profile) = &self.shared.profile {
+            profile.browser.set(Some(pid));
+        }
+    }
+}
+
+impl Shared {
+    fn created(&self, env: Result<webview2::Environment, String>) {
+        let next = match &env {
+            Ok(env) => {
+                trace_event!(debug, "environment created");
+                self.generation.set(self.generation.get() + 1);
+                State::Ready(env.clone())
+            }
+            Err(_) => State::Idle
+        };
+        let waiters = match mem::replace(&mut *self.state.borrow_mut(), next) {
+            State::Creating(waiters) => waiters,
+            _ => Vec::new()
+        };
+        let generation = self.generation.get();
+        for f in waiters {
+            f(env.clone().map(|env| (env, generation)));
+        }
+    }
+}
+
+/// How long a dropped [`TempProfile`] waits for the browser process to let go of it.
+const BROWSER_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+const PROFILE_PREFIX: &str = "webviewx-profile-";
+
+/// The temporary user data directory of an ephemeral profile, removed on drop.
+///
+/// The browser process holds the profile's files until it exits, so once it started, the
+/// profile is removed by a background thread waiting for it. Directories left behind anyway,
+/// e.g. because the application exited first or was killed, are removed by the first
+/// `TempProfile` of a later run.
+pub(crate) struct TempProfile {
+    path: PathBuf,
+    browser: Cell<Option<u32>>,
+}
+
+impl TempProfile {
+    pub(crate) fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        static LEFTOVERS: Once = Once::new();
+        let temp = std::env::temp_dir();
+        LEFTOVERS.call_once(|| remove_leftovers(&temp));
+        let name = format!("{}{}-{}", PROFILE_PREFIX, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        TempProfile { path: temp.join(name), browser: Cell::new(None) }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempProfile {
+    fn drop(&mut self) {
+        let path = mem::take(&mut self.path);
+        let pid = match self.browser.get() {
+            Some(pid) => pid,
+            None => return remove_profile(&path)
+        };
+        // Not on the UI thread, the browser may take seconds to exit.
+        let spawned = thread::Builder::new().name("webviewx-profile".to_owned()).spawn(move || {
+            wait_for_exit(pid, BROWSER_EXIT_TIMEOUT);
+            remove_profile(&path);
+        });
+        if spawned.is_err() {
+            log::warn!("the ephemeral profile is left for the next run to remove");
+        }
+    }
+}
+
+fn remove_profile(path: &Path) {
+    // Children of the browser process may take a moment longer than the browser itself.
+    for _ in 0..10 {
+        match fs::remove_dir_all(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => thread::sleep(Duration::from_millis(100)),
+            _ => return
+        }
+    }
+    log::warn!("the ephemeral profile {} could not be removed", path.display());
+}
+
+/// Removes the profiles below `temp` whose process is gone.
+fn remove_leftovers(temp: &Path) {
+    let entries = match fs::read_dir(temp) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+    for entry in entries.flatten() {
+        let pid = entry.file_name().to_str().and_then(profile_pid);
+        if pid.is_some_and(|pid| pid != std::process::id() && !process_running(pid)) {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+/// The process id in the name of a [`TempProfile`] directory.
+fn profile_pid(name: &str) -> Option<u32> {
+    let (pid, n) = name.strip_prefix(PROFILE_PREFIX)?.split_once('-')?;
+    n.parse::<u64>().ok()?;
+    pid.parse().ok()
+}
+
+fn process_running(pid: u32) -> bool {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if process.is_null() {
+            return false
+        }
+        let mut code: DWORD = 0;
+        let running = GetExitCodeProcess(process, &mut code) != 0 && code == STILL_ACTIVE;
+        CloseHandle(process);
+        running
+    }
+}
+
+fn wait_for_exit(pid: u32, timeout: Duration) {
+    unsafe {
+        let process = OpenProcess(SYNCHRONIZE, FALSE, pid);
+        if !process.is_null() {
+            WaitForSingleObject(process, timeout.as_millis() as DWORD);
+            CloseHandle(process);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_arguments() {
+        let env = BrowserEnvironment::new().argument("--disable-gpu");
+        assert_eq!(env.browser_arguments(), ["--disable-gpu"]);
+
+        let env = BrowserEnvironment::new().proxy(Proxy::Server {
+            server: "proxy:8080".to_owned(),
+            bypass: vec!["<local>".to_owned(), "*.example.com".to_owned()],
+        });
+        assert_eq!(env.browser_arguments(), ["--proxy-server=proxy:8080", "--proxy-bypass-list=<local>;*.example.com"]);
+        assert_eq!(BrowserEnvironment::new().proxy(Proxy::Direct).browser_arguments(), ["--no-proxy-server"]);
+        let pac = BrowserEnvironment::new().proxy(Proxy::Pac("http://wpad/wpad.dat".to_owned()));
+        assert_eq!(pac.browser_arguments(), ["--proxy-pac-url=http://wpad/wpad.dat"]);
+    }
+
+    #[test]
+    fn user_data_dir() {
+        let local = Path::new("AppData").join("Local");
+        let dir = default_user_data_dir(Some(local.clone().into()), Some(Path::new("App").join("app.exe")));
+        assert_eq!(dir, Some(local.join("app").join("WebView2")));
+        assert_eq!(default_user_data_dir(None, Some("app.exe".into())), None);
+
+        let env = BrowserEnvironment::new().user_data_dir("profile");
+        assert_eq!(env.persistent_user_data_dir(), Some(PathBuf::from("profile")));
+
+        let profile = TempProfile::new();
+        std::fs::create_dir_all(profile.path().join("Default")).unwrap();
+        let path = profile.path().to_owned();
+        drop(profile);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn leftover_profiles() {
+        assert_eq!(profile_pid("webviewx-profile-1234-0"), Some(1234));
+        assert_eq!(profile_pid("webviewx-profile-1234"), None);
+        assert_eq!(profile_pid("webviewx-assets-1234-0"), None);
+
+        let temp = std::env::temp_dir().join(format!("webviewx-leftovers-{}", std::process::id()));
+        let gone = temp.join(format!("{}{}-0", PROFILE_PREFIX, u32::MAX));
+        let ours = temp.join(format!("{}{}-7", PROFILE_PREFIX, std::process::id()));
+        let other = temp.join("webviewx-assets-1");
+        for dir in &[&gone, &ours, &other] {
+            fs::create_dir_all(dir.join("Default")).unwrap();
+        }
+        remove_leftovers(&temp);
+        assert_eq!((gone.exists(), ours.exists(), other.exists()), (false, true, true));
+        fs::remove_dir_all(&temp).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Error pages for failed navigations.
+//!
+//! With an [`error_page`](../struct.WebViewBuilder.html#method.error_page) hook, a main-frame
+//! navigation that fails shows the application's HTML instead of the engine's page. Cancelled
+//! navigations show nothing. The page can call `webviewx.retry()` to load the failed URL again.
+//!
+//! MSHTML has no navigation errors to hook into; its own error pages from `res://ieframe.dll`
+//! are recognized once they report in and then replaced, so they may flash briefly.
+use crate::navigation::NavigationError;
+
+/// Called with the failed URL and the error, returns the HTML to show.
+pub type ErrorPageHandler = Box<dyn FnMut(&str, NavigationError) -> String>;
+
+/// The built-in error page: what went wrong, the URL and a Retry button.
+pub fn template(url: &str, error: NavigationError) -> String {
+    let message = error.to_string();
+    let mut chars = message.chars();
+    let message: String = chars.next().map(|c| c.to_ascii_uppercase()).into_iter().chain(chars).collect();
+    format!(r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="X-UA-Compatible" content="IE=edge">
+<title>Page not available</title>
+<style>
+  body {{ margin: 0; font: 14px/1.5 "Segoe UI", sans-serif; color: #333; background: #fff; }}
+  main {{ max-width: 32em; margin: 15% auto 0; padding: 0 1.5em; }}
+  h1 {{ font-size: 1.5em; font-weight: normal; }}
+  .url {{ color: #777; word-wrap: break-word; }}
+  button {{ font: inherit; padding: .3em 1.5em; }}
+</style>
+</head>
+<body>
+<main>
+  <h1>This page is not available</h1>
+  <p>{message}.</p>
+  <p class="url">{url}</p>
+  <button onclick="window.webviewx ? webviewx.retry() : location.reload()">Retry</button>
+</main>
+</body>
+</html>
+"#, message = escape(&message), url = escape(url))
+}
+
+/// The failed URL and the error behind an MSHTML error page such as
+/// `res://ieframe.dll/dnserror.htm#http://example.com/`.
+pub(crate) fn mshtml_error(url: &str) -> Option<(&str, NavigationError)> {
+    const PREFIX: &str = "res://ieframe.dll/";
+    if !url.get(..PREFIX.len())?.eq_ignore_ascii_case(PREFIX) {
+        return None
+    }
+    let (page, failed) = url[PREFIX.len()..].split_once('#')?;
+    let page = page.to_ascii_lowercase();
+    let error = if page.starts_with("dnserror") {
+        // Shown for unresolved hosts and refused connections alike.
+        NavigationError::CannotConnect
+    } else if page.starts_with("navcancl") {
+        NavigationError::ConnectionLost
+    } else if ["invalidcert", "certerror", "sslnavcancel"].iter().any(|p| page.starts_with(p)) {
+        NavigationError::Certificate
+    } else if page.starts_with("http_") {
+        // Friendly HTTP error pages: the server did answer.
+        return None
+    } else {
+        NavigationError::Unknown
+    };
+    Some((failed, error))
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_template() {
+        let html = template("http://localhost:8080/?a=1&b=<2>", NavigationError::CannotConnect);
+        assert!(html.contains("<p>The connection could not be established.</p>"));
+        assert!(html.contains("http://localhost:8080/?a=1&amp;b=&lt;2&gt;"));
+        assert!(html.contains("webviewx.retry()"));
+    }
+
+    #[test]
+    fn mshtml_error_pages() {
+        assert_eq!(
+            mshtml_error("res://ieframe.dll/dnserrordiagoff.htm#http://localhost:8080/#top"),
+            Some(("http://localhost:8080/#top", NavigationError::CannotConnect))
+        );
+        assert_eq!(
+            mshtml_error("RES://ieframe.dll/navcancl.htm#https://example.com/"),
+            Some(("https://example.com/", NavigationError::ConnectionLost))
+        );
+        assert_eq!(mshtml_error("res://ieframe.dll/invalidcert.htm#https://a/").map(|e| e.1), Some(NavigationError::Certificate));
+        assert_eq!(mshtml_error("res://ieframe.dll/http_404.htm#http://a/"), None);
+        assert_eq!(mshtml_error("http://example.com/#res://ieframe.dll/"), None);
+        assert_eq!(mshtml_error("res://ieframe.dll/acr_error.htm"), None);
+    }
+}
@@ -0,0 +1,432 @@
+//! State kept for the legacy MSHTML engine.
+//!
+//! MSHTML has no equivalent of `AddScriptToExecuteOnDocumentCreated`, so the document
+//! scripts are inlined into HTML content and re-injected after every navigation: `step()`
+//! periodically asks the page whether it already ran them, and the page answers through
+//! `external.invoke` when it did not.
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::{mem, ptr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use winapi::Interface;
+use winapi::ctypes::c_void;
+use winapi::shared::basetsd::DWORD_PTR;
+use winapi::shared::guiddef::{GUID, REFIID};
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPARAM, LPVOID, TRUE, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::shared::ntdef::HRESULT;
+use winapi::shared::wtypes::BSTR;
+use winapi::um::exdisp::IWebBrowser2;
+use winapi::um::oleauto::{SysFreeString, SysStringLen};
+use winapi::um::servprov::IServiceProvider;
+use winapi::um::unknwnbase::IUnknown;
+use winapi::um::winnt::LPCSTR;
+use winapi::um::wininet::{INTERNET_OPEN_TYPE_DIRECT, INTERNET_OPEN_TYPE_PROXY, INTERNET_OPTION_PROXY};
+use winapi::um::winuser::{EnumChildWindows, GetClassNameW, RegisterWindowMessageW, SendMessageTimeoutW, SMTO_ABORTIFHUNG};
+use crate::assets::AssetResolver;
+use crate::{bridge, compat, error_page, SelectionReport};
+use crate::console::Console;
+use crate::environment::Proxy;
+use crate::error_page::ErrorPageHandler;
+use crate::navigation::Navigation;
+use crate::policy::{NavigationPolicy, PolicyRequest};
+use crate::script::{self, EvalCallback, EvalError, InitScripts};
+use crate::server::{self, AssetServer};
+use crate::watch::Watcher;
+
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// State of an MSHTML webview, the user data of [`WebView::WV1`](../enum.WebView.html).
+///
+/// Its contents are internal; it is public so the variant's type can be named.
+pub struct Legacy {
+    pub(crate) scripts: Vec<String>,
+    /// The application's init scripts, run after `scripts`.
+    pub(crate) init_scripts: InitScripts,
+    last_probe: Option<Instant>,
+    /// Serves the builder's assets for as long as the webview lives.
+    pub(crate) server: Option<AssetServer>,
+    pub(crate) watcher: Option<Watcher>,
+    navigation: Navigation,
+    pub(crate) policy: Option<NavigationPolicy>,
+    /// Pages that may move, resize and close the window. `None` unless it is frameless.
+    pub(crate) window_commands: Option<NavigationPolicy>,
+    /// URL the policy allowed on `navigate:`, so its document is not checked again.
+    approved: Option<String>,
+    /// Id of the last document that reported in.
+    document: u64,
+    /// Scheme of the builder's assets, served by `server`.
+    pub(crate) scheme: Option<String>,
+    /// The builder's assets, for `fetch` requests of the compatibility layer.
+    pub(crate) assets: Option<Arc<dyn AssetResolver>>,
+    /// Whether the compatibility layer is enabled.
+    pub(crate) compat: bool,
+    pub(crate) report: SelectionReport,
+    pub(crate) error_page: Option<ErrorPageHandler>,
+    pub(crate) console: Console,
+    /// URL of the last failed navigation, for `webviewx.retry()`.
+    failed_url: Option<String>,
+    /// Whether the next document is an error page shown by us.
+    showing_error_page: bool,
+    /// Documents seen so far and the index of the current one. MSHTML does not expose its
+    /// history, so it is tracked from the documents reporting in.
+    history: Vec<String>,
+    position: usize,
+    /// History step requested through `go_back()`/`go_forward()`.
+    pending_step: Option<isize>,
+    /// Callbacks of `eval()` calls waiting for their reply, by id.
+    evals: HashMap<String, EvalCallback>,
+}
+
+impl Legacy {
+    pub(crate) fn new(scripts: Vec<String>, navigation: Navigation) -> Self {
+        Legacy {
+            scripts,
+            init_scripts: InitScripts::default(),
+            last_probe: None,
+            server: None,
+            watcher: None,
+            navigation,
+            policy: None,
+            window_commands: None,
+            approved: None,
+            document: 0,
+            scheme: None,
+            assets: None,
+            compat: false,
+            report: SelectionReport::default(),
+            error_page: None,
+            console: Console::new(None),
+            failed_url: None,
+            showing_error_page: false,
+            history: Vec::new(),
+            position: 0,
+            pending_step: None,
+            evals: HashMap::new(),
+        }
+    }
+
+    /// All document scripts as one snippet that marks the page as initialized.
+    fn init_script(&self) -> String {
+        let mut js = String::from("window.__webviewx_init = true;\n");
+        for s in &self.scripts {
+            js.push_str(s);
+            js.push('\n');
+        }
+        // Separately, so one failing script does not stop the others.
+        for (_, s) in self.init_scripts.iter() {
+            js.push_str(&format!("try {{ (0, eval)({}); }} catch (e) {{}}\n", bridge::js_string(s)));
+        }
+        js
+    }
+
+    /// Inlines the document scripts into `html`, preceded by the `X-UA-Compatible` meta tag
+    /// if the compatibility layer is enabled.
+    pub(crate) fn inject_into_html(&self, html: &str) -> String {
+        let meta = if self.compat { compat::META } else { "" };
+        insert_into_head(html, &format!("{}<script>{}</script>", meta, self.init_script()))
+    }
+
+    /// Runs `js` and passes the outcome to `callback` once the page replies.
+    pub(crate) fn eval(wv: &mut web_view::WebView<Legacy>, js: &str, callback: EvalCallback) {
+        // Unguessable, so the page cannot answer in the host's place.
+        let id = match server::new_token() {
+            Ok(id) => id,
+            Err(e) => return callback(Err(EvalError::Engine(e.to_string())))
+        };
+        // The reply can arrive before `eval()` returns.
+        wv.user_data_mut().evals.insert(id.clone(), callback);
+        if let Err(e) = wv.eval(&script::posted(js, &id)) {
+            if let Some(callback) = wv.user_data_mut().evals.remove(&id) {
+                callback(Err(EvalError::Engine(e.to_string())));
+            }
+        }
+    }
+
+    /// URL prefixes of the builder's assets: the scheme and the loopback server.
+    fn asset_origins(&self) -> Vec<String> {
+        let scheme = self.scheme.iter().map(|scheme| format!("{}://", scheme));
+        scheme.chain(self.server.iter().map(AssetServer::origin)).collect()
+    }
+
+    /// Resolves `<scheme>://` URLs of the builder's assets to the loopback server.
+    pub(crate) fn resolve_url(&self, url: &str) -> String {
+        let path = self.scheme.as_ref().and_then(|scheme| url.strip_prefix(scheme.as_str())?.strip_prefix("://"));
+        match (path, &self.server) {
+            (Some(path), Some(server)) => server.url(path),
+            _ => url.to_owned()
+        }
+    }
+
+    pub(crate) fn can_go_back(&self) -> bool {
+        self.position > 0
+    }
+
+    pub(crate) fn can_go_forward(&self) -> bool {
+        self.position + 1 < self.history.len()
+    }
+
+    pub(crate) fn current_url(&self) -> Option<String> {
+        self.history.get(self.position).cloned()
+    }
+
+    /// Remembers that the next document comes from stepping through the history.
+    pub(crate) fn step_history(&mut self, step: isize) {
+        self.pending_step = Some(step);
+    }
+
+    /// Updates the history with a document that reported in.
+    fn record(&mut self, url: &str) {
+        let before = (self.can_go_back(), self.can_go_forward());
+        match self.pending_step.take() {
+            Some(step) if !self.history.is_empty() => {
+                self.position = (self.position as isize + step).clamp(0, self.history.len() as isize - 1) as usize;
+                self.history[self.position] = url.to_owned();
+            }
+            _ if self.current_url().as_deref() == Some(url) => {}
+            _ => {
+                self.history.truncate(self.position + 1);
+                self.history.push(url.to_owned());
+                self.position = self.history.len() - 1;
+            }
+        }
+        let after = (self.can_go_back(), self.can_go_forward());
+        if before != after {
+            self.navigation.history_changed(after.0, after.1);
+        }
+    }
+
+    /// Applies the navigation policy, if any.
+    fn check(&mut self, url: &str, new_window: bool, user_initiated: bool) -> bool {
+        match &mut self.policy {
+            Some(policy) => crate::allow_navigation(policy, &PolicyRequest { url, new_window, user_initiated }),
+            None => true
+        }
+    }
+
+    /// Called from `WebView::step()`.
+    pub(crate) fn step(wv: &mut web_view::WebView<Legacy>) {
+        let state = wv.user_data_mut();
+        if state.scripts.is_empty() || state.last_probe.is_some_and(|t| t.elapsed() < PROBE_INTERVAL) {
+            return
+        }
+        state.last_probe = Some(Instant::now());
+        let probe = format!("if (!window.__webviewx_init) window.external.invoke('{}init');", bridge::PREFIX);
+        let _ = wv.eval(&probe);
+    }
+
+    /// `external.invoke` handler.
+    ///
+    /// MSHTML has no navigation events; a navigation is reported once the new document runs
+    /// the bridge script, and completes on its `load` event. Any script on the page can post
+    /// to the bridge, so reports and window commands are checked against the URL the browser
+    /// itself shows.
+    pub(crate) fn invoke(wv: &mut web_view::WebView<Legacy>, arg: &str) -> web_view::WVResult {
+        let _span = trace_span!(debug, "ipc", engine = "mshtml", command = bridge::command(arg), size = arg.len());
+        match arg.strip_prefix(bridge::PREFIX) {
+            Some("init") => {
+                let js = wv.user_data().init_script();
+                return wv.eval(&js)
+            }
+            Some("load") => {
+                let state = wv.user_data_mut();
+                state.navigation.completed(state.document, None);
+                return Ok(())
+            }
+            Some(cmd) if cmd.starts_with("dom:") => {
+                let url = &cmd[4..];
+                let actual = location_url(wv.window_handle() as HWND).unwrap_or_default();
+                let failed = error_page::mshtml_error(url).map(|(failed, _)| failed);
+                if !bridge::same_document(url, &actual) && !failed.is_some_and(|f| bridge::same_document(f, &actual)) {
+                    log::warn!("ignoring a report of {} while the browser shows {:?}", url, actual);
+                    return Ok(())
+                }
+                let state = wv.user_data_mut();
+                if std::mem::take(&mut state.showing_error_page) {
+                    return Ok(())
+                }
+                let approved = state.approved.take().is_some_and(|a| a.trim_end_matches('/') == url.trim_end_matches('/'));
+                if let Some((failed, error)) = error_page::mshtml_error(url) {
+                    state.document += 1;
+                    state.navigation.starting(state.document, failed, false);
+                    state.navigation.completed(state.document, Some(error));
+                    let html = match &mut state.error_page {
+                        Some(f) => f(failed, error),
+                        None => return Ok(())
+                    };
+                    let html = state.inject_into_html(&html);
+                    state.failed_url = Some(failed.to_owned());
+                    state.showing_error_page = true;
+                    return wv.set_html(&html)
+                }
+                if !approved && !state.check(url, false, false) {
+                    // Too late to cancel, leave the page instead.
+                    return wv.eval("history.length > 1 ? history.back() : location.replace('about:blank');")
+                }
+                for (_, callback) in state.evals.drain() {
+                    callback(Err(EvalError::Cancelled));
+                }
+                state.document += 1;
+                state.navigation.starting(state.document, url, false);
+                state.navigation.dom_content_loaded(Some(state.document));
+                state.record(url);
+                return Ok(())
+            }
+            Some(cmd) if cmd.starts_with("console:") => {
+                wv.user_data_mut().console.received(&cmd[8..]);
+                return Ok(())
+            }
+            Some("retry") => {
+                return match wv.user_data().failed_url.clone() {
+                    Some(url) => wv.eval(&format!("location.href = {};", bridge::js_string(&url))),
+                    None => Ok(())
+                }
+            }
+            Some(cmd) if cmd.starts_with("fetch:") => {
+                let state = wv.user_data();
+                let reply = compat::fetch(state.assets.as_deref(), &state.asset_origins(), &cmd[6..]);
+                return reply.map_or(Ok(()), |js| wv.eval(&js))
+            }
+            Some(cmd) if cmd.starts_with("eval:") => {
+                if let Some((id, result)) = script::parse_posted(&cmd[5..]) {
+                    if let Some(callback) = wv.user_data_mut().evals.remove(id) {
+                        callback(result);
+                    }
+                }
+                return Ok(())
+            }
+            Some(cmd) if cmd.starts_with("navigate:") || cmd.starts_with("open:") => {
+                let (kind, url) = cmd.split_once(':').unwrap_or_default();
+                let state = wv.user_data_mut();
+                if state.check(url, kind == "open", true) {
+                    state.approved = Some(url.to_owned());
+                    return wv.eval(&format!("location.href = {};", bridge::js_string(url)))
+                }
+                return Ok(())
+            }
+            _ => {}
+        }
+        let hwnd = wv.window_handle() as HWND;
+        // The page's own idea of its URL cannot be trusted with the window.
+        let url = if arg.starts_with(bridge::PREFIX) { location_url(hwnd).unwrap_or_default() } else { String::new() };
+        bridge::handle(hwnd, arg, &url, wv.user_data().window_commands.as_ref());
+        Ok(())
+    }
+}
+
+/// Inserts `snippet` right after `<head>` or `<!doctype>`, so the document mode is not
+/// affected.
+pub(crate) fn insert_into_head(html: &str, snippet: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let at = ["<head", "<!doctype"]
+        .iter()
+        .filter_map(|tag| lower.find(tag))
+        .filter_map(|start| lower[start..].find('>').map(|end| start + end + 1))
+        .next()
+        .unwrap_or(0);
+    let mut out = String::with_capacity(html.len() + snippet.len());
+    out.push_str(&html[..at]);
+    out.push_str(snippet);
+    out.push_str(&html[at..]);
+    out
+}
+
+/// `INTERNET_PROXY_INFO`, which `UrlMkSetSessionOption` only takes with ANSI strings.
+#[repr(C)]
+struct ProxyInfo {
+    access_type: DWORD,
+    proxy: LPCSTR,
+    bypass: LPCSTR,
+}
+
+#[link(name = "urlmon")]
+extern "system" {
+    fn UrlMkSetSessionOption(option: DWORD, buffer: LPVOID, length: DWORD, reserved: DWORD) -> HRESULT;
+}
+
+/// Sets the proxy of MSHTML for the whole process. [`Proxy::System`] keeps the system's
+/// settings, and [`Proxy::Pac`] has no session option, so both leave them alone.
+pub(crate) fn set_proxy(proxy: &Proxy) {
+    let (access_type, server, bypass) = match proxy {
+        Proxy::Direct => (INTERNET_OPEN_TYPE_DIRECT, None, None),
+        Proxy::Server { server, bypass } => {
+            match (CString::new(server.as_str()), CString::new(bypass.join(";"))) {
+                (Ok(server), Ok(bypass)) => (INTERNET_OPEN_TYPE_PROXY, Some(server), Some(bypass)),
+                _ => return
+            }
+        }
+        Proxy::System | Proxy::Pac(_) => return
+    };
+    let mut info = ProxyInfo {
+        access_type,
+        proxy: server.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+        bypass: bypass.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+    };
+    unsafe {
+        UrlMkSetSessionOption(INTERNET_OPTION_PROXY, &mut info as *mut ProxyInfo as LPVOID, mem::size_of::<ProxyInfo>() as DWORD, 0);
+    }
+}
+
+/// `IHTMLDocument2`, as handed out by `WM_HTML_GETOBJECT`.
+const IID_IHTML_DOCUMENT2: GUID = GUID {
+    Data1: 0x332c4425, Data2: 0x26cb, Data3: 0x11d0, Data4: [0xb4, 0x83, 0x00, 0xc0, 0x4f, 0xd9, 0x01, 0x19],
+};
+/// `SID_SWebBrowserApp`, the service of the browser hosting a document.
+const SID_WEB_BROWSER_APP: GUID = GUID {
+    Data1: 0x0002df05, Data2: 0x0000, Data3: 0x0000, Data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+};
+
+#[link(name = "oleacc")]
+extern "system" {
+    fn ObjectFromLresult(result: DWORD_PTR, riid: REFIID, w_param: WPARAM, object: *mut *mut c_void) -> HRESULT;
+}
+
+/// URL of the top-level document MSHTML shows in `hwnd`, from `IWebBrowser2` rather than
+/// from what the page reports. `None` before the browser window exists.
+fn location_url(hwnd: HWND) -> Option<String> {
+    unsafe extern "system" fn find_server(child: HWND, found: LPARAM) -> BOOL {
+        let mut class = [0u16; 32];
+        let n = GetClassNameW(child, class.as_mut_ptr(), class.len() as i32) as usize;
+        if String::from_utf16_lossy(&class[..n]) == "Internet Explorer_Server" {
+            *(found as *mut HWND) = child;
+            return FALSE
+        }
+        TRUE
+    }
+    /// Releases `object` and passes on the outcome of the call made through it.
+    unsafe fn released<T>(object: *mut IUnknown, hr: HRESULT, out: *mut T) -> Option<*mut T> {
+        (*object).Release();
+        if hr < 0 || out.is_null() { None } else { Some(out) }
+    }
+    unsafe {
+        let mut server: HWND = ptr::null_mut();
+        EnumChildWindows(hwnd, Some(find_server), &mut server as *mut HWND as LPARAM);
+        if server.is_null() {
+            return None
+        }
+        let name: Vec<u16> = "WM_HTML_GETOBJECT".encode_utf16().chain(Some(0)).collect();
+        let msg = RegisterWindowMessageW(name.as_ptr());
+        let mut result: DWORD_PTR = 0;
+        if SendMessageTimeoutW(server, msg, 0, 0, SMTO_ABORTIFHUNG, 1000, &mut result) == 0 {
+            return None
+        }
+        let mut document: *mut c_void = ptr::null_mut();
+        if ObjectFromLresult(result, &IID_IHTML_DOCUMENT2, 0, &mut document) < 0 || document.is_null() {
+            return None
+        }
+        let document = document as *mut IUnknown;
+        let mut services: *mut c_void = ptr::null_mut();
+        let hr = (*document).QueryInterface(&IServiceProvider::uuidof(), &mut services);
+        let services = released(document, hr, services as *mut IServiceProvider)?;
+        let mut browser: *mut c_void = ptr::null_mut();
+        let hr = (*services).QueryService(&SID_WEB_BROWSER_APP, &IWebBrowser2::uuidof(), &mut browser);
+        let browser = released(services as *mut IUnknown, hr, browser as *mut IWebBrowser2)?;
+        let mut url: BSTR = ptr::null_mut();
+        let hr = (*browser).get_LocationURL(&mut url);
+        let url = released(browser as *mut IUnknown, hr, url)?;
+        let location = String::from_utf16_lossy(std::slice::from_raw_parts(url, SysStringLen(url) as usize));
+        SysFreeString(url);
+        Some(location)
+    }
+}
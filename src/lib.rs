@@ -1,9 +1,42 @@
 use std::error::Error;
 use winapi::shared::windef::HWND;
 use std::ffi::{CStr, CString};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fmt::{Debug, Formatter, Display};
+use std::sync::Arc;
+use raw_window_handle::{HasRawWindowHandle, HasRawDisplayHandle, RawWindowHandle, RawDisplayHandle, WindowsDisplayHandle};
+#[macro_use]
+mod trace;
+pub mod assets;
+mod bridge;
+mod compat;
+pub mod console;
+#[cfg(feature = "embed")]
+pub mod embed;
+pub mod environment;
+pub mod error_page;
+mod legacy;
+pub mod navigation;
+pub mod policy;
+pub mod recovery;
+pub mod script;
+mod server;
+pub mod style;
+mod watch;
 mod wv2;
+mod window;
+mod window_state;
+
+pub use assets::{Asset, AssetResolver, EmbeddedAssets};
+pub use console::{ConsoleLevel, ConsoleMessage};
+pub use environment::{BrowserEnvironment, Environment, Proxy};
+pub use legacy::Legacy;
+pub use navigation::{NavigationCompleted, NavigationError, NavigationStarting};
+pub use policy::{NavigationPolicy, PolicyDecision, PolicyRequest};
+pub use recovery::{ProcessFailure, RecoveryAction, RecoveryPolicy};
+pub use script::{EvalError, JsException, ScriptId};
+pub use style::CssId;
+pub use window_state::WindowState;
 
 #[derive(Debug)]
 pub enum WVError {
@@ -25,6 +58,7 @@ impl From<web_view::Error> for WVError {
 }
 
 pub fn install_webview2(confirm:Option<&str>, wv2_folder:Option<&Path>) -> bool {
+    let _span = trace_span!(info, "install_webview2");
     if webview2::get_available_browser_version_string(wv2_folder).is_err() {
         use std::io::Write;
         use std::os::windows::process::CommandExt;
@@ -32,6 +66,7 @@ pub fn install_webview2(confirm:Option<&str>, wv2_folder:Option<&Path>) -> bool
         if let Some(m) = confirm {
             use tinyfiledialogs::*;
             if let OkCancel::Cancel = message_box_ok_cancel("", m, MessageBoxIcon::Question, OkCancel::Ok) {
+                trace_event!(info, "installation declined");
                 return false
             }
         }
@@ -54,23 +89,94 @@ pub fn install_webview2(confirm:Option<&str>, wv2_folder:Option<&Path>) -> bool
             .unwrap();
         drop(stdin);
         let r = p.wait().unwrap();
+        trace_event!(info, success = r.success(), "installer finished");
         r.success()
     } else {
+        trace_event!(debug, "runtime installed");
         true
     }
 }
 
 pub type WVResult<T=()> = Result<T,WVError>;
 
+/// Includes the assets bundled by [`embed::Bundle`] in the build script as [`EmbeddedAssets`].
+///
+/// ```ignore
+/// WebViewBuilder::new().assets("app", webview_x::embed_assets!("dist"))
+/// ```
+///
+/// [`embed::Bundle`]: embed/struct.Bundle.html
+/// [`EmbeddedAssets`]: assets/struct.EmbeddedAssets.html
+#[macro_export]
+macro_rules! embed_assets {
+    ($name:literal) => {{
+        use $crate::assets::{EmbeddedAssets, EmbeddedFile};
+        const FILES: &[EmbeddedFile] = include!(concat!(env!("OUT_DIR"), "/webviewx-assets-", $name, ".rs"));
+        EmbeddedAssets::new(FILES)
+    }};
+}
+
+/// Whether the builder runs in development mode, see [`WebViewBuilder::dev_url()`].
+///
+/// The `WEBVIEWX_DEV` environment variable decides when set (`1` or `0`); otherwise debug
+/// builds are in development mode.
+///
+/// [`WebViewBuilder::dev_url()`]: struct.WebViewBuilder.html#method.dev_url
+pub fn dev_mode() -> bool {
+    match std::env::var("WEBVIEWX_DEV") {
+        Ok(v) => v != "0" && !v.is_empty(),
+        Err(_) => cfg!(debug_assertions)
+    }
+}
+
+/// Opens `url` with the default handler, e.g. the system browser.
+pub(crate) fn open_external(url: &str) {
+    use winapi::um::{shellapi::ShellExecuteW, winuser::SW_SHOWNORMAL};
+    let wide = |s: &str| s.encode_utf16().chain(std::iter::once(0)).collect::<Vec<u16>>();
+    let (verb, url) = (wide("open"), wide(url));
+    unsafe {
+        ShellExecuteW(std::ptr::null_mut(), verb.as_ptr(), url.as_ptr(), std::ptr::null(), std::ptr::null(), SW_SHOWNORMAL);
+    }
+}
+
+/// Applies `policy` to a navigation. Returns whether it may go ahead.
+pub(crate) fn allow_navigation(policy: &mut NavigationPolicy, request: &PolicyRequest) -> bool {
+    match policy.decide(request) {
+        PolicyDecision::Allow => true,
+        PolicyDecision::Block => false,
+        PolicyDecision::OpenExternally => {
+            open_external(request.url);
+            false
+        }
+    }
+}
+
+/// `scheme://authority` of `url`.
+fn origin_of(url: &str) -> Option<&str> {
+    let start = url.find("://")? + 3;
+    let end = url[start..].find(['/', '?', '#']).map_or(url.len(), |i| start + i);
+    Some(&url[..end])
+}
+
+/// Whether `s` is a URL rather than inline HTML.
+pub(crate) fn is_url(s: &str) -> bool {
+    s.find("://").is_some_and(|i| i < 10)
+}
+
 #[derive(Copy,Clone)]
 pub enum WebViewMode {
     ///Suggestion webview2
     /// ex:)
     /// Auto(Some("WebView2 is not installed. WebView2 will provide a better experience. Do you want install?")) => Suggestion install webview2. if installation failed then fallback to legaycy mode
     /// Auto(None) => Not suggestion installing webview2 but try install. if installation failed then fallback to legaycy mode
+    ///
+    /// A runtime that is installed but fails to start also falls back to MSHTML, see
+    /// [`WebView::selection_report()`](enum.WebView.html#method.selection_report).
     Auto(Option<&'static str>),
 
     ///if webview2 not available then we use legacy MSHTML
+    ///
+    /// This includes a runtime that fails to start.
     Fallback,
 
     ///Force legacy MSHTML
@@ -80,17 +186,110 @@ pub enum WebViewMode {
     WebView2(Option<&'static str>)
 }
 
+/// A browser engine.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Engine {
+    WebView2,
+    MSHTML,
+}
+
+/// An engine [`WebViewBuilder::build()`] tried, and why it was not used.
+///
+/// [`WebViewBuilder::build()`]: struct.WebViewBuilder.html#method.build
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct EngineAttempt {
+    pub engine: Engine,
+    /// `None` for the engine that was used.
+    pub error: Option<String>,
+}
+
+/// How [`WebViewBuilder::build()`] picked the engine, see [`WebView::selection_report()`].
+///
+/// [`WebViewBuilder::build()`]: struct.WebViewBuilder.html#method.build
+/// [`WebView::selection_report()`]: enum.WebView.html#method.selection_report
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct SelectionReport {
+    /// In the order they were tried. The last one is the engine in use.
+    pub attempts: Vec<EngineAttempt>,
+}
+
+impl SelectionReport {
+    /// The engine in use.
+    pub fn engine(&self) -> Option<Engine> {
+        self.attempts.last().filter( |a| a.error.is_none() ).map( |a| a.engine )
+    }
+
+    fn failed(&mut self, engine: Engine, error: String) {
+        trace_event!(warn, engine = ?engine, error = %error, "engine failed");
+        self.attempts.push( EngineAttempt { engine, error: Some(error) } );
+    }
+
+    fn succeeded(&mut self, engine: Engine) {
+        trace_event!(info, engine = ?engine, "engine selected");
+        self.attempts.push( EngineAttempt { engine, error: None } );
+    }
+}
+
+/// Position and size of a window in logical pixels.
+///
+/// For a child window the position is relative to the parent's client area.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Bounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Monitor used to center a window.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Monitor {
+    /// The primary monitor.
+    Primary,
+    /// The monitor the window is currently on.
+    Current,
+}
+
 pub struct WebViewBuilder<'a> {
     pub engine : WebViewMode,
     pub background_color : (u8,u8,u8,u8),
     pub title : &'a str,
     pub url : &'a str,
+    /// Whether DevTools and the default context menus are available.
     pub debug : bool,
     pub width: i32,
     pub height: i32,
     pub resizable: bool,
     pub invoke_handler: Option<fn (&mut WebView, data:&str)>,
     pub frameless: bool,
+    pub resize_border: i32,
+    pub parent: Option<RawWindowHandle>,
+    pub bounds: Option<Bounds>,
+    pub position: Option<(i32,i32)>,
+    pub center: Option<Monitor>,
+    pub min_size: Option<(i32,i32)>,
+    pub max_size: Option<(i32,i32)>,
+    pub maximized: bool,
+    pub minimized: bool,
+    pub fullscreen: bool,
+    pub always_on_top: bool,
+    pub window_state: Option<&'a str>,
+    pub assets: Option<(&'a str, Arc<dyn AssetResolver>)>,
+    pub dev_url: Option<&'a str>,
+    pub watch: Vec<PathBuf>,
+    pub navigation: navigation::NavigationHandlers,
+    pub policy: Option<NavigationPolicy>,
+    /// Init scripts and whether they only run in the main frame.
+    pub init_scripts: Vec<(String, bool)>,
+    pub legacy_compat: bool,
+    pub on_process_failed: Option<recovery::ProcessFailedHandler>,
+    pub recovery: RecoveryPolicy,
+    pub error_page: Option<error_page::ErrorPageHandler>,
+    pub capture_console: bool,
+    pub remote_debugging_port: Option<u16>,
+    pub on_console_message: Option<console::ConsoleHandler>,
+    pub environment: BrowserEnvironment,
+    pub shared_environment: Option<Environment>,
 }
 
 impl <'a> Default for WebViewBuilder<'_> {
@@ -100,12 +299,39 @@ impl <'a> Default for WebViewBuilder<'_> {
             background_color : (255,255,255,255),
             title : "No title",
             url : "about:blank",
-            debug : false,
+            debug : cfg!(debug_assertions),
             width: 800,
             height: 600,
             resizable: true,
             invoke_handler: None,
             frameless: false,
+            resize_border: 5,
+            parent: None,
+            bounds: None,
+            position: None,
+            center: None,
+            min_size: None,
+            max_size: None,
+            maximized: false,
+            minimized: false,
+            fullscreen: false,
+            always_on_top: false,
+            window_state: None,
+            assets: None,
+            dev_url: None,
+            watch: Vec::new(),
+            navigation: Default::default(),
+            policy: None,
+            init_scripts: Vec::new(),
+            legacy_compat: false,
+            on_process_failed: None,
+            recovery: RecoveryPolicy::default(),
+            error_page: None,
+            capture_console: false,
+            remote_debugging_port: None,
+            on_console_message: None,
+            environment: BrowserEnvironment::default(),
+            shared_environment: None,
         }
     }
 }
@@ -149,6 +375,121 @@ impl <'a> WebViewBuilder<'a> {
         self
     }
 
+    /// Sets the initial position of the WebView window in logical pixels.
+    ///
+    /// Defaults to the system's choice.
+    pub fn position(mut self, x: i32, y: i32) -> Self {
+        self.position = Some((x, y));
+        self
+    }
+
+    /// Centers the WebView window on the given monitor. Takes precedence over [`position()`].
+    ///
+    /// [`position()`]: struct.WebViewBuilder.html#method.position
+    pub fn center(mut self, monitor: Monitor) -> Self {
+        self.center = Some(monitor);
+        self
+    }
+
+    /// Sets the minimum size the user can resize the window to, in logical pixels.
+    pub fn min_size(mut self, width: i32, height: i32) -> Self {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    /// Sets the maximum size the user can resize the window to, in logical pixels.
+    pub fn max_size(mut self, width: i32, height: i32) -> Self {
+        self.max_size = Some((width, height));
+        self
+    }
+
+    /// Shows the window maximized.
+    ///
+    /// Defaults to `false`.
+    pub fn maximized(mut self, maximized: bool) -> Self {
+        self.maximized = maximized;
+        self
+    }
+
+    /// Shows the window minimized.
+    ///
+    /// Defaults to `false`.
+    pub fn minimized(mut self, minimized: bool) -> Self {
+        self.minimized = minimized;
+        self
+    }
+
+    /// Starts in borderless fullscreen on the window's monitor.
+    ///
+    /// Defaults to `false`.
+    pub fn fullscreen(mut self, fullscreen: bool) -> Self {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    /// Keeps the window above all non-topmost windows.
+    ///
+    /// Defaults to `false`.
+    pub fn always_on_top(mut self, always_on_top: bool) -> Self {
+        self.always_on_top = always_on_top;
+        self
+    }
+
+    /// Saves the window size, position and maximized state when the window closes and
+    /// restores them on the next `build()`. The state is stored under `%APPDATA%\<app_name>`.
+    ///
+    /// A saved placement takes precedence over [`position()`] and [`center()`].
+    ///
+    /// [`position()`]: struct.WebViewBuilder.html#method.position
+    /// [`center()`]: struct.WebViewBuilder.html#method.center
+    pub fn remember_window_state(mut self, app_name: &'a str) -> Self {
+        self.window_state = Some(app_name);
+        self
+    }
+
+    /// Allows DevTools, including [`WebView::open_devtools()`], and the engine's default
+    /// context menus.
+    ///
+    /// Defaults to `true` in debug builds and `false` in release builds.
+    ///
+    /// [`WebView::open_devtools()`]: enum.WebView.html#method.open_devtools
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Lets DevTools protocol clients, e.g. test automation, attach to the webview on
+    /// `http://127.0.0.1:<port>`. Independent of [`debug()`](#method.debug), and never
+    /// enabled by default.
+    ///
+    /// WebView2 only. The port belongs to the browser process, which is shared by the
+    /// webviews of the application.
+    pub fn remote_debugging_port(mut self, port: u16) -> Self {
+        self.remote_debugging_port = Some(port);
+        self
+    }
+
+    /// Profile directory, language, proxy and command line of the browser, see
+    /// [`BrowserEnvironment`].
+    ///
+    /// WebView2 takes all of them. MSHTML only takes the proxy, and not a [`Proxy::Pac`];
+    /// it applies to every MSHTML webview of the process.
+    pub fn environment(mut self, environment: BrowserEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Shares `environment` with the other webviews built with it: one WebView2 browser
+    /// process, profile, cookies and storage for all of them. Its settings apply instead of
+    /// [`environment()`](#method.environment) and
+    /// [`remote_debugging_port()`](#method.remote_debugging_port).
+    ///
+    /// Without it every webview starts its own browser process.
+    pub fn shared_environment(mut self, environment: &Environment) -> Self {
+        self.shared_environment = Some(environment.clone());
+        self
+    }
+
     /// Sets the resizability of the WebView window. If set to false, the window cannot be resized.
     ///
     /// Defaults to `true`.
@@ -159,12 +500,207 @@ impl <'a> WebViewBuilder<'a> {
 
     /// The window crated will be frameless
     ///
+    /// The page moves the window through drag regions: elements with the `webviewx-drag`
+    /// class or `data-webviewx-drag` attribute (opt out with `data-webviewx-no-drag`).
+    /// Custom title bars can call `webviewx.minimize()`, `webviewx.maximize()` and
+    /// `webviewx.close()`. These commands only work for the start URL's origin, the
+    /// [`assets()`](#method.assets) and the [`navigation_policy()`](#method.navigation_policy)
+    /// allowlist; other pages and windows with a frame cannot move or close the window.
+    ///
     /// defaults to `false`
     pub fn frameless(mut self, frameless: bool) -> Self {
         self.frameless = frameless;
         self
     }
 
+    /// Width in CSS pixels of the page border that resizes a frameless window.
+    /// Set to 0 to disable; ignored when the window is not resizable.
+    ///
+    /// Defaults to `5`.
+    pub fn resize_border(mut self, resize_border: i32) -> Self {
+        self.resize_border = resize_border;
+        self
+    }
+
+    /// Embeds the WebView as a child of `parent` instead of creating a top-level window.
+    ///
+    /// `bounds` is relative to the parent's client area. Only Win32 window handles are supported.
+    pub fn parent<W: HasRawWindowHandle>(mut self, parent: &W, bounds: Bounds) -> Self {
+        self.parent = Some(parent.raw_window_handle());
+        self.bounds = Some(bounds);
+        self
+    }
+
+    /// Serves application assets from `resolver` under a custom URI scheme, e.g. `app`.
+    /// Relative references in the page then resolve against the scheme like on a web server.
+    ///
+    /// Point [`url()`] at `<scheme>://index.html` to load the application. WebView2 only
+    /// intercepts http(s) requests, so the page actually lives at `https://<scheme>.localhost/`.
+    /// MSHTML cannot intercept requests at all; it is pointed at a loopback server on
+    /// `127.0.0.1` that only answers requests carrying a per-session secret token.
+    ///
+    /// [`url()`]: struct.WebViewBuilder.html#method.url
+    pub fn assets<R: AssetResolver + 'static>(mut self, scheme: &'a str, resolver: R) -> Self {
+        self.assets = Some((scheme, Arc::new(resolver)));
+        self
+    }
+
+    /// URL loaded in [development mode](fn.dev_mode.html), e.g. a Vite dev server, instead of
+    /// [`url()`] and [`assets()`], which then act as the production source.
+    ///
+    /// [`url()`]: struct.WebViewBuilder.html#method.url
+    /// [`assets()`]: struct.WebViewBuilder.html#method.assets
+    pub fn dev_url(mut self, url: &'a str) -> Self {
+        self.dev_url = Some(url);
+        self
+    }
+
+    /// In [development mode](fn.dev_mode.html), reloads the page when a file below `path`
+    /// changes. Can be called several times.
+    pub fn watch<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.watch.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Called before a navigation of the main frame starts. The callback can cancel it.
+    pub fn on_navigation_starting<F: FnMut(&mut NavigationStarting) + 'static>(mut self, f: F) -> Self {
+        self.navigation.starting = Some(Box::new(f));
+        self
+    }
+
+    /// Called with the URL once the new document starts loading.
+    pub fn on_content_loading<F: FnMut(&str) + 'static>(mut self, f: F) -> Self {
+        self.navigation.content_loading = Some(Box::new(f));
+        self
+    }
+
+    /// Called with the URL once the new document is parsed.
+    pub fn on_dom_content_loaded<F: FnMut(&str) + 'static>(mut self, f: F) -> Self {
+        self.navigation.dom_content_loaded = Some(Box::new(f));
+        self
+    }
+
+    /// Called once per navigation, after the page loaded or the navigation failed.
+    ///
+    /// The events of a navigation always arrive in the order starting, content loading, DOM
+    /// content loaded, completed. Failed or cancelled navigations skip to completed.
+    pub fn on_navigation_completed<F: FnMut(&NavigationCompleted) + 'static>(mut self, f: F) -> Self {
+        self.navigation.completed = Some(Box::new(f));
+        self
+    }
+
+    /// Restricts where the webview may navigate and which new windows it may open.
+    ///
+    /// The start URL's origin, the [`assets()`] origin and inline HTML content are always
+    /// allowed. On MSHTML the policy is best effort: link clicks and `window.open` are checked
+    /// before they happen, other navigations once the new document reports in.
+    ///
+    /// [`assets()`]: struct.WebViewBuilder.html#method.assets
+    pub fn navigation_policy(mut self, policy: NavigationPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Called with `can_go_back` and `can_go_forward` when they change.
+    pub fn on_history_changed<F: FnMut(bool, bool) + 'static>(mut self, f: F) -> Self {
+        self.navigation.history_changed = Some(Box::new(f));
+        self
+    }
+
+    /// Forwards the page's console output, uncaught errors and unhandled promise rejections
+    /// to the `log` crate, under the `webview::console` target.
+    ///
+    /// See the [`console`](console/index.html) module for how it is captured.
+    pub fn capture_console(mut self, capture_console: bool) -> Self {
+        self.capture_console = capture_console;
+        self
+    }
+
+    /// Captures the console like [`capture_console()`](#method.capture_console) and also
+    /// passes every record to `f`.
+    pub fn on_console_message<F: FnMut(&ConsoleMessage) + 'static>(mut self, f: F) -> Self {
+        self.capture_console = true;
+        self.on_console_message = Some(Box::new(f));
+        self
+    }
+
+    /// When a navigation of the main frame fails, shows the HTML `f` returns for the failed
+    /// URL and error instead of the engine's error page. The page can call `webviewx.retry()`
+    /// to load the URL again.
+    ///
+    /// See the [`error_page`](error_page/index.html) module for how this works on MSHTML.
+    pub fn error_page<F: FnMut(&str, NavigationError) -> String + 'static>(mut self, f: F) -> Self {
+        self.error_page = Some(Box::new(f));
+        self
+    }
+
+    /// Shows the built-in [`error_page::template()`] with a Retry button when a navigation
+    /// fails, see [`error_page()`](#method.error_page).
+    ///
+    /// [`error_page::template()`]: error_page/fn.template.html
+    pub fn default_error_page(self) -> Self {
+        self.error_page(error_page::template)
+    }
+
+    /// Called when a browser process of the webview fails, before the
+    /// [`recovery_policy()`](#method.recovery_policy) brings the page back.
+    ///
+    /// Only WebView2 runs the page in other processes; on MSHTML this is never called.
+    pub fn on_process_failed<F: FnMut(ProcessFailure) + 'static>(mut self, f: F) -> Self {
+        self.on_process_failed = Some(Box::new(f));
+        self
+    }
+
+    /// Sets how to recover when the page's process fails: reload it, recreate the webview
+    /// or show an error page, with backoff between attempts.
+    ///
+    /// Defaults to [`RecoveryPolicy::default()`], three reloads.
+    ///
+    /// [`RecoveryPolicy::default()`]: recovery/struct.RecoveryPolicy.html#method.new
+    pub fn recovery_policy(mut self, policy: RecoveryPolicy) -> Self {
+        self.recovery = policy;
+        self
+    }
+
+    /// Runs `js` at the start of every document, before the page's own scripts. Can be called
+    /// several times; the scripts run in the order they were added.
+    ///
+    /// The scripts get the ids listed by [`WebView::init_scripts()`], so they can be removed
+    /// later. On WebView2 they also run in iframes. MSHTML has no document-start hook: the
+    /// scripts are inlined into HTML content, but pages loaded from a URL only get them once
+    /// the document is running, and never in frames.
+    ///
+    /// [`WebView::init_scripts()`]: enum.WebView.html#method.init_scripts
+    pub fn init_script(mut self, js: &str) -> Self {
+        self.init_scripts.push((js.to_owned(), false));
+        self
+    }
+
+    /// Like [`init_script()`](#method.init_script), but only runs in the top-level document.
+    pub fn main_frame_init_script(mut self, js: &str) -> Self {
+        self.init_scripts.push((js.to_owned(), true));
+        self
+    }
+
+    /// Enables the compatibility layer for when the webview runs on MSHTML: HTML content asks
+    /// for the newest document mode through `X-UA-Compatible`, and `Promise`, `fetch`,
+    /// `Object.assign` and `CustomEvent` are polyfilled. `fetch` requests for the
+    /// [`assets()`] are answered directly from the resolver.
+    ///
+    /// Like the other document scripts, the polyfills arrive late in pages loaded from a URL,
+    /// except for HTML served from [`assets()`]; compressed HTML needs an
+    /// [`identity`](assets/struct.Asset.html#structfield.identity) copy for that, which
+    /// embedded bundles have. Pages can check `webviewx.engine` and
+    /// `webviewx.documentMode` to see what they run on. Has no effect on WebView2.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`assets()`]: struct.WebViewBuilder.html#method.assets
+    pub fn legacy_compat(mut self, legacy_compat: bool) -> Self {
+        self.legacy_compat = legacy_compat;
+        self
+    }
+
     /// Sets the invoke handler callback. This will be called when a message is received from
     /// JavaScript.
     ///
@@ -179,7 +715,41 @@ impl <'a> WebViewBuilder<'a> {
     }
 
     /// Validates provided arguments and returns a new WebView if successful.
-    pub fn build(self) -> WVResult<WebView<'a>> {
+    pub fn build(mut self) -> WVResult<WebView<'a>> {
+        let _span = trace_span!(info, "build");
+        let dev = dev_mode();
+        if let Some(url) = self.dev_url.filter( |_| dev ) {
+            self.url = url;
+            self.assets = None;
+        }
+        let watch = if dev { std::mem::take( &mut self.watch ) } else { Vec::new() };
+        let policy = self.policy.take();
+        let own_content = |mut policy: NavigationPolicy| {
+            match origin_of( self.url ) {
+                Some(origin) => policy = policy.allow( origin ),
+                None => policy = policy.allow( "about:blank" ).allow( "data:text/html*" )
+            }
+            if let Some((scheme, _)) = &self.assets {
+                policy = policy.allow( &assets::origin( scheme ) );
+            }
+            policy
+        };
+        let mut policy = policy.map( own_content );
+
+        let parent = match self.parent {
+            Some(RawWindowHandle::Win32(h)) => Some(h.hwnd as HWND),
+            Some(_) => return Err(WVError::Cause("unsupported parent window handle")),
+            None => None
+        };
+        // Only the application's own pages and the allowlisted ones may move, resize and
+        // close a frameless window.
+        let mut window_commands = if self.frameless && parent.is_none() {
+            Some( policy.as_ref().map_or_else( || own_content( NavigationPolicy::new() ), NavigationPolicy::allowlist ) )
+        } else {
+            None
+        };
+
+        let mut report = SelectionReport::default();
         let wv2_installed = match self.engine {
             WebViewMode::WebView2(msg) => {
                 if !install_webview2(msg, None) {
@@ -195,6 +765,12 @@ impl <'a> WebViewBuilder<'a> {
             }
             _ => false
         };
+        if !wv2_installed && !matches!( self.engine, WebViewMode::MSHTML ) {
+            report.failed( Engine::WebView2, "the WebView2 runtime is not installed".to_owned() );
+        }
+
+        let bridge = bridge::script( self.frameless, if self.resizable { self.resize_border } else { 0 } );
+        let console = if self.capture_console { Some( console::script() ) } else { None };
 
         if wv2_installed {
             //we can use webview2
@@ -217,22 +793,121 @@ impl <'a> WebViewBuilder<'a> {
                 unsafe { MessageBoxW(hwnd, text.as_ptr(), caption.as_ptr(), _type) }
             }
 
-            let wv2 = wv2::WebView2Builder::new()
+            let mut wv2 = wv2::WebView2Builder::new()
                 .title( self.title )
                 .url( self.url )
                 .size( self.width, self.height )
                 .resizable( self.resizable )
-                .build()?;
-
-            return Ok(
-                WebView::WV2(wv2)
-            )
+                .frameless( self.frameless && parent.is_none() )
+                .script( &bridge )
+                .visible( false )
+                .navigation( std::mem::take( &mut self.navigation ) );
+            for (js, main_frame_only) in &self.init_scripts {
+                wv2 = wv2.init_script( js, *main_frame_only );
+            }
+            if let Some(policy) = policy.take() {
+                wv2 = wv2.policy( policy );
+            }
+            if let Some(trusted) = &window_commands {
+                wv2 = wv2.window_commands( trusted.allowlist() );
+            }
+            if let Some(f) = self.on_process_failed.take() {
+                wv2 = wv2.on_process_failed( f );
+            }
+            wv2 = wv2.recovery( self.recovery.clone() ).debug( self.debug );
+            if let Some(port) = self.remote_debugging_port {
+                wv2 = wv2.remote_debugging_port( port );
+            }
+            wv2 = wv2.environment( self.environment.clone() );
+            if let Some(environment) = &self.shared_environment {
+                wv2 = wv2.shared_environment( environment.clone() );
+            }
+            if let Some(f) = self.error_page.take() {
+                wv2 = wv2.error_page( f );
+            }
+            if let Some(js) = &console {
+                wv2 = wv2.script( js );
+            }
+            if let Some(f) = self.on_console_message.take() {
+                wv2 = wv2.on_console_message( f );
+            }
+            if let Some((scheme, resolver)) = &self.assets {
+                wv2 = wv2.assets( scheme, resolver.clone() );
+            }
+            if let (Some(parent), Some(bounds)) = (parent, self.bounds) {
+                wv2 = wv2.parent( parent, bounds );
+            }
+            match wv2.create() {
+                Ok(mut wv2) => {
+                    report.succeeded( Engine::WebView2 );
+                    wv2.report = report;
+                    if !watch.is_empty() {
+                        wv2.watch( watch );
+                    }
+                    let mut wv2 = WebView::WV2( wv2 );
+                    self.show_window( &mut wv2 );
+                    return Ok( wv2 )
+                }
+                // Nothing to fall back from, the user is gone.
+                Err(e) if e.closed => {
+                    return Err(WVError::Cause("the window was closed before WebView2 was ready"))
+                }
+                // A broken runtime or a policy blocking it: move on to MSHTML.
+                Err(e) if !matches!( self.engine, WebViewMode::WebView2(_) ) => {
+                    report.failed( Engine::WebView2, e.reason );
+                    self.navigation = e.navigation;
+                    policy = e.policy;
+                    self.error_page = e.error_page;
+                    self.on_console_message = e.on_console_message;
+                }
+                Err(e) => {
+                    message_box( ptr::null_mut(), &format!( "Creating WebView2 failed: {}\n", e.reason ), "Error", MB_ICONERROR | MB_OK );
+                    return Err(WVError::Cause("Creating WebView2 failed"))
+                }
+            }
         }
 
-        let url = if self.url[ .. 10.min(self.url.len()-1)].find("://").is_none() {
-            web_view::Content::Html( self.url )
+        let settings = self.shared_environment.as_ref().map_or( &self.environment, |e| e.settings() );
+        legacy::set_proxy( &settings.proxy );
+        let navigation = navigation::Navigation::new( std::mem::take( &mut self.navigation ) );
+        let mut legacy = legacy::Legacy::new( vec![bridge], navigation );
+        report.succeeded( Engine::MSHTML );
+        legacy.report = report;
+        legacy.error_page = self.error_page.take();
+        legacy.console = console::Console::new( self.on_console_message.take() );
+        legacy.scripts.extend( console );
+        for (js, main_frame_only) in &self.init_scripts {
+            legacy.init_scripts.add( js, *main_frame_only );
+        }
+        if self.legacy_compat {
+            legacy.compat = true;
+            legacy.scripts.insert( 0, compat::polyfills() );
+        }
+        let mut url = self.url.to_owned();
+        if let Some((scheme, resolver)) = &self.assets {
+            // MSHTML cannot serve a custom scheme, go through the loopback server instead.
+            let served = if self.legacy_compat { compat::assets( resolver.clone() ) } else { resolver.clone() };
+            let server = server::AssetServer::start( served )
+                .map_err( |_| WVError::Cause("starting the asset server failed") )?;
+            if let Some(path) = self.url.strip_prefix( scheme ).and_then( |rest| rest.strip_prefix("://") ) {
+                url = server.url( path );
+            }
+            legacy.scheme = Some( scheme.to_string() );
+            legacy.assets = Some( resolver.clone() );
+            legacy.policy = policy.map( |p| p.allow( &server.origin() ) );
+            window_commands = window_commands.map( |p| p.allow( &server.origin() ) );
+            legacy.server = Some( server );
+        } else {
+            legacy.policy = policy;
+        }
+        if legacy.policy.is_some() {
+            legacy.scripts.push( bridge::policy_script() );
+        }
+        legacy.window_commands = window_commands;
+        let url = if is_url( &url ) {
+            web_view::Content::Url( url )
         } else {
-            web_view::Content::Url( self.url )
+            web_view::Content::Html( legacy.inject_into_html( &url ) )
         };
         let mut wv_legacy = web_view::WebViewBuilder::new()
             .title( self.title )
@@ -240,19 +915,68 @@ impl <'a> WebViewBuilder<'a> {
             .size( self.width, self.height )
             .resizable( self.resizable )
             .debug( self.debug )
-            .frameless( self.frameless )
-            .user_data( () )
-            .invoke_handler( |_,_| { Ok(())} )
+            .visible( false )
+            .user_data( legacy )
+            .invoke_handler( legacy::Legacy::invoke )
             .build()?;
         wv_legacy.set_color( self.background_color );
-        Ok( WebView::WV1( wv_legacy ) )
+        if !watch.is_empty() {
+            let handle = wv_legacy.handle();
+            wv_legacy.user_data_mut().watcher = Some( watch::Watcher::start( watch, move || {
+                let _ = handle.dispatch( |wv| wv.eval("location.reload()") );
+            } ) );
+        }
+        if let (Some(parent), Some(bounds)) = (parent, self.bounds) {
+            window::attach_to_parent( wv_legacy.window_handle() as HWND, parent, bounds );
+        } else if self.frameless {
+            window::set_frameless( wv_legacy.window_handle() as HWND, true );
+        }
+        let mut wv_legacy = WebView::WV1( wv_legacy );
+        self.show_window( &mut wv_legacy );
+        Ok( wv_legacy )
 
     }
+
+    /// Applies the window geometry options to the still hidden window and shows it.
+    fn show_window(&self, webview: &mut WebView) {
+        let hwnd = webview.hwnd();
+        if self.parent.is_some() {
+            window::show( hwnd, false, false );
+            return
+        }
+        if let Some((x, y)) = self.position {
+            window::set_position( hwnd, x, y );
+        }
+        if let Some(monitor) = self.center {
+            window::center( hwnd, monitor );
+        }
+        let mut maximized = self.maximized;
+        if let Some(path) = self.window_state.and_then( WindowState::path ) {
+            if let Some(state) = WindowState::load( &path ) {
+                maximized = window_state::restore( hwnd, &state );
+            }
+            window::set_state_file( hwnd, Some(path) );
+        }
+        if self.min_size.is_some() {
+            window::set_min_size( hwnd, self.min_size );
+        }
+        if self.max_size.is_some() {
+            window::set_max_size( hwnd, self.max_size );
+        }
+        if self.always_on_top {
+            window::set_always_on_top( hwnd, true );
+        }
+        window::show( hwnd, maximized, self.minimized );
+        if self.fullscreen {
+            window::set_fullscreen( hwnd, true );
+        }
+    }
 }
 
 
 pub enum WebView<'a> {
-    WV1( web_view::WebView<'a, ()> ),
+    /// MSHTML, with the crate's state as user data.
+    WV1( web_view::WebView<'a, Legacy> ),
     WV2( wv2::WebView2 )
 }
 
@@ -260,6 +984,7 @@ impl <'a> WebView<'a> {
     pub fn step(&mut self) {
         match self {
             WebView::WV1( wv) => {
+                legacy::Legacy::step( wv );
                 wv.step();
             }
             WebView::WV2( wv) => {
@@ -268,6 +993,193 @@ impl <'a> WebView<'a> {
         }
     }
 
+    /// Loads `url`. `<scheme>://` URLs of the builder's [`assets()`] are supported.
+    ///
+    /// [`assets()`]: struct.WebViewBuilder.html#method.assets
+    pub fn navigate(&mut self, url: &str) -> WVResult {
+        match self {
+            WebView::WV1( wv) => {
+                let url = wv.user_data().resolve_url( url );
+                Ok( wv.eval( &format!( "location.href = {};", bridge::js_string( &url ) ) )? )
+            }
+            WebView::WV2( wv) => wv.navigate( url )
+        }
+    }
+
+    /// Shows `html` as a new document.
+    pub fn load_html(&mut self, html: &str) -> WVResult {
+        match self {
+            WebView::WV1( wv) => {
+                let html = wv.user_data().inject_into_html( html );
+                Ok( wv.set_html( &html )? )
+            }
+            WebView::WV2( wv) => wv.load_html( html )
+        }
+    }
+
+    pub fn reload(&mut self) -> WVResult {
+        match self {
+            WebView::WV1( wv) => Ok( wv.eval( "location.reload();" )? ),
+            WebView::WV2( wv) => wv.reload()
+        }
+    }
+
+    pub fn stop(&mut self) -> WVResult {
+        match self {
+            WebView::WV1( wv) => Ok( wv.eval( "window.stop ? window.stop() : document.execCommand('Stop');" )? ),
+            WebView::WV2( wv) => wv.stop()
+        }
+    }
+
+    pub fn go_back(&mut self) -> WVResult {
+        match self {
+            WebView::WV1( wv) => {
+                if !wv.user_data().can_go_back() {
+                    return Ok(())
+                }
+                wv.user_data_mut().step_history( -1 );
+                Ok( wv.eval( "history.back();" )? )
+            }
+            WebView::WV2( wv) => wv.go_back()
+        }
+    }
+
+    pub fn go_forward(&mut self) -> WVResult {
+        match self {
+            WebView::WV1( wv) => {
+                if !wv.user_data().can_go_forward() {
+                    return Ok(())
+                }
+                wv.user_data_mut().step_history( 1 );
+                Ok( wv.eval( "history.forward();" )? )
+            }
+            WebView::WV2( wv) => wv.go_forward()
+        }
+    }
+
+    /// On MSHTML the history is tracked from the pages the webview showed, so history
+    /// navigations the page does on its own are not accounted for.
+    pub fn can_go_back(&self) -> bool {
+        match self {
+            WebView::WV1( wv) => wv.user_data().can_go_back(),
+            WebView::WV2( wv) => wv.can_go_back()
+        }
+    }
+
+    /// See [`can_go_back()`](#method.can_go_back).
+    pub fn can_go_forward(&self) -> bool {
+        match self {
+            WebView::WV1( wv) => wv.user_data().can_go_forward(),
+            WebView::WV2( wv) => wv.can_go_forward()
+        }
+    }
+
+    /// Runs `js` in the page's global scope and passes its value, converted to JSON, or the
+    /// exception it threw to `callback`. Promises are not awaited.
+    ///
+    /// WebView2 answers asynchronously, from the message loop. On MSHTML the value travels
+    /// back through `external.invoke` and needs `JSON`, which old document modes lack.
+    pub fn eval<F: FnOnce(Result<serde_json::Value, EvalError>) + 'static>(&mut self, js: &str, callback: F) {
+        let _started = trace::now();
+        let callback = move |result: Result<serde_json::Value, EvalError>| {
+            trace_event!(debug, elapsed_us = _started.elapsed().as_micros() as u64, ok = result.is_ok(), "eval answered");
+            callback( result )
+        };
+        match self {
+            WebView::WV1( wv) => legacy::Legacy::eval( wv, js, Box::new( callback ) ),
+            WebView::WV2( wv) => wv.eval( js, Box::new( callback ) )
+        }
+    }
+
+    /// Adds an init script, see [`WebViewBuilder::init_script()`]. It runs from the next
+    /// document on.
+    ///
+    /// [`WebViewBuilder::init_script()`]: struct.WebViewBuilder.html#method.init_script
+    pub fn add_init_script(&mut self, js: &str, main_frame_only: bool) -> ScriptId {
+        match self {
+            WebView::WV1( wv) => wv.user_data_mut().init_scripts.add( js, main_frame_only ),
+            WebView::WV2( wv) => wv.add_init_script( js, main_frame_only )
+        }
+    }
+
+    /// Removes init script `id` from the next document on; the current document keeps
+    /// whatever it did. Returns `false` if there is no such script.
+    pub fn remove_init_script(&mut self, id: ScriptId) -> bool {
+        match self {
+            WebView::WV1( wv) => wv.user_data_mut().init_scripts.remove( id ),
+            WebView::WV2( wv) => wv.remove_init_script( id )
+        }
+    }
+
+    /// Ids of the init scripts in the order they run. The builder's scripts come first, in
+    /// the order they were added.
+    pub fn init_scripts(&self) -> Vec<ScriptId> {
+        match self {
+            WebView::WV1( wv) => wv.user_data().init_scripts.ids(),
+            WebView::WV2( wv) => wv.init_scripts()
+        }
+    }
+
+    /// Adds `css` as a stylesheet to the current document and every following one, in the
+    /// main frame. The stylesheet is applied through an init script, which shows up in
+    /// [`init_scripts()`](#method.init_scripts).
+    pub fn inject_css(&mut self, css: &str) -> CssId {
+        let element = style::next_element();
+        let js = style::insert( element, css );
+        let script = self.add_init_script( &js, true );
+        self.eval( &js, |_| {} );
+        CssId { element, script }
+    }
+
+    /// Removes a stylesheet added with [`inject_css()`](#method.inject_css). Returns `false`
+    /// if it was already removed.
+    pub fn remove_css(&mut self, id: CssId) -> bool {
+        if !self.remove_init_script( id.script ) {
+            return false
+        }
+        self.eval( &style::remove( id.element ), |_| {} );
+        true
+    }
+
+    /// Adds a stylesheet setting CSS custom properties on `:root`, e.g. theme colors from a map
+    /// of names to values. Remove it with [`remove_css()`](#method.remove_css) to change them.
+    /// See [`style::root_variables()`] and [`inject_css()`](#method.inject_css).
+    ///
+    /// [`style::root_variables()`]: style/fn.root_variables.html
+    pub fn inject_css_variables<I, K, V>(&mut self, vars: I) -> CssId
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.inject_css( &style::root_variables( vars ) )
+    }
+
+    /// Opens the DevTools window. Fails if the builder did not allow
+    /// [`debug()`](struct.WebViewBuilder.html#method.debug), and on MSHTML, which has none.
+    pub fn open_devtools(&mut self) -> WVResult {
+        match self {
+            WebView::WV1( _) => Err(WVError::Cause("MSHTML has no DevTools")),
+            WebView::WV2( wv) => wv.open_devtools()
+        }
+    }
+
+    /// Which engine the webview runs on and which ones were tried before.
+    pub fn selection_report(&self) -> &SelectionReport {
+        match self {
+            WebView::WV1( wv) => &wv.user_data().report,
+            WebView::WV2( wv) => &wv.report
+        }
+    }
+
+    /// URL of the current document, if known yet.
+    pub fn current_url(&self) -> Option<String> {
+        match self {
+            WebView::WV1( wv) => wv.user_data().current_url(),
+            WebView::WV2( wv) => wv.current_url()
+        }
+    }
+
     pub fn exit(&mut self) {
         match self {
             WebView::WV1( wv) => {
@@ -278,4 +1190,90 @@ impl <'a> WebView<'a> {
             }
         }
     }
+
+    /// Native handle of the window hosting the webview.
+    pub fn hwnd(&self) -> HWND {
+        match self {
+            WebView::WV1( wv) => wv.window_handle() as HWND,
+            WebView::WV2( wv) => wv.hwnd()
+        }
+    }
+
+    /// Moves and resizes the window. For a child WebView `bounds` is relative to the parent.
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        match self {
+            WebView::WV1( wv) => {
+                window::set_window_bounds( wv.window_handle() as HWND, bounds );
+            }
+            WebView::WV2( wv) => {
+                wv.set_bounds( bounds );
+            }
+        }
+    }
+
+    /// Moves the window to `x`, `y` in logical pixels.
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        window::set_position( self.hwnd(), x, y );
+    }
+
+    /// Resizes the window to `width` x `height` in logical pixels.
+    pub fn set_size(&mut self, width: i32, height: i32) {
+        window::set_size( self.hwnd(), width, height );
+    }
+
+    /// Centers the window on the given monitor.
+    pub fn center(&mut self, monitor: Monitor) {
+        window::center( self.hwnd(), monitor );
+    }
+
+    /// Sets or clears the minimum window size in logical pixels.
+    pub fn set_min_size(&mut self, size: Option<(i32,i32)>) {
+        window::set_min_size( self.hwnd(), size );
+    }
+
+    /// Sets or clears the maximum window size in logical pixels.
+    pub fn set_max_size(&mut self, size: Option<(i32,i32)>) {
+        window::set_max_size( self.hwnd(), size );
+    }
+
+    pub fn set_maximized(&mut self, maximized: bool) {
+        window::set_maximized( self.hwnd(), maximized );
+    }
+
+    pub fn set_minimized(&mut self, minimized: bool) {
+        window::set_minimized( self.hwnd(), minimized );
+    }
+
+    /// Enters or leaves borderless fullscreen on the window's monitor.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        window::set_fullscreen( self.hwnd(), fullscreen );
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        window::is_fullscreen( self.hwnd() )
+    }
+
+    pub fn set_always_on_top(&mut self, always_on_top: bool) {
+        window::set_always_on_top( self.hwnd(), always_on_top );
+    }
+
+    /// Current placement of the window, as it would be saved by
+    /// [`WebViewBuilder::remember_window_state()`].
+    ///
+    /// [`WebViewBuilder::remember_window_state()`]: struct.WebViewBuilder.html#method.remember_window_state
+    pub fn window_state(&self) -> WindowState {
+        window_state::capture( self.hwnd() )
+    }
+}
+
+unsafe impl HasRawWindowHandle for WebView<'_> {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        window::raw_window_handle( self.hwnd() )
+    }
+}
+
+unsafe impl HasRawDisplayHandle for WebView<'_> {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        RawDisplayHandle::Windows( WindowsDisplayHandle::empty() )
+    }
 }
\ No newline at end of file
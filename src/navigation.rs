@@ -0,0 +1,347 @@
+//! Navigation lifecycle events.
+//!
+//! The engines report navigations differently: WebView2 has events (but no DOMContentLoaded
+//! in the SDK we bind), MSHTML only tells us when a new document runs the bridge script.
+//! [`Navigation`] turns whatever the engine reports into the same sequence for every
+//! navigation:
+//!
+//! `starting` → `content_loading` → `dom_content_loaded` → `completed`
+//!
+//! Missing steps of a successful navigation are filled in, late or duplicate reports are
+//! dropped, and every `starting` is matched by exactly one `completed`. A navigation that is
+//! cancelled, superseded or fails completes with an error and skips the remaining steps.
+use std::fmt::{self, Display, Formatter};
+
+/// Passed to [`on_navigation_starting`](../struct.WebViewBuilder.html#method.on_navigation_starting).
+#[derive(Debug)]
+pub struct NavigationStarting {
+    pub url: String,
+    /// Whether the user started the navigation, e.g. by clicking a link.
+    pub user_initiated: bool,
+    cancel: bool,
+}
+
+impl NavigationStarting {
+    /// Cancels the navigation. It then completes with [`NavigationError::Cancelled`].
+    ///
+    /// MSHTML reports navigations only once the new document runs, so this has no effect there.
+    pub fn cancel(&mut self) {
+        self.cancel = true;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+    }
+}
+
+/// Passed to [`on_navigation_completed`](../struct.WebViewBuilder.html#method.on_navigation_completed).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NavigationCompleted {
+    pub url: String,
+    /// `None` on success.
+    pub error: Option<NavigationError>,
+}
+
+impl NavigationCompleted {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Why a navigation failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NavigationError {
+    /// The certificate is invalid, expired, revoked or for another host.
+    Certificate,
+    HostNameNotResolved,
+    CannotConnect,
+    ServerUnreachable,
+    Timeout,
+    /// The connection was aborted, reset or disconnected.
+    ConnectionLost,
+    InvalidResponse,
+    RedirectFailed,
+    /// Cancelled by the application, the user or a newer navigation.
+    Cancelled,
+    Unknown,
+}
+
+impl Display for NavigationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NavigationError::Certificate => "the certificate is not valid",
+            NavigationError::HostNameNotResolved => "the host name could not be resolved",
+            NavigationError::CannotConnect => "the connection could not be established",
+            NavigationError::ServerUnreachable => "the server is unreachable",
+            NavigationError::Timeout => "the connection timed out",
+            NavigationError::ConnectionLost => "the connection was lost",
+            NavigationError::InvalidResponse => "the server sent an invalid response",
+            NavigationError::RedirectFailed => "the redirect failed",
+            NavigationError::Cancelled => "the navigation was cancelled",
+            NavigationError::Unknown => "the navigation failed",
+        })
+    }
+}
+
+pub type StartingHandler = Box<dyn FnMut(&mut NavigationStarting)>;
+pub type UrlHandler = Box<dyn FnMut(&str)>;
+pub type CompletedHandler = Box<dyn FnMut(&NavigationCompleted)>;
+/// Called with `can_go_back` and `can_go_forward`.
+pub type HistoryHandler = Box<dyn FnMut(bool, bool)>;
+
+/// Navigation callbacks of a [`WebViewBuilder`](../struct.WebViewBuilder.html).
+#[derive(Default)]
+pub struct NavigationHandlers {
+    pub starting: Option<StartingHandler>,
+    pub content_loading: Option<UrlHandler>,
+    pub dom_content_loaded: Option<UrlHandler>,
+    pub completed: Option<CompletedHandler>,
+    pub history_changed: Option<HistoryHandler>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Started,
+    ContentLoading,
+    DomContentLoaded,
+}
+
+struct Current {
+    id: u64,
+    url: String,
+    stage: Stage,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    started: crate::trace::Start,
+}
+
+/// Orders the engine's navigation reports, see the [module documentation](index.html).
+pub(crate) struct Navigation {
+    handlers: NavigationHandlers,
+    current: Option<Current>,
+}
+
+impl Navigation {
+    pub(crate) fn new(handlers: NavigationHandlers) -> Self {
+        Navigation { handlers, current: None }
+    }
+
+    /// URL of navigation `id` while it is in progress.
+    pub(crate) fn url(&self, id: u64) -> Option<&str> {
+        self.current.as_ref().filter(|c| c.id == id).map(|c| c.url.as_str())
+    }
+
+    pub(crate) fn into_handlers(self) -> NavigationHandlers {
+        self.handlers
+    }
+
+    /// Returns `true` if the navigation is cancelled. Redirects keep the `id` of the navigation
+    /// they belong to and are reported as another `starting` with the new URL.
+    pub(crate) fn starting(&mut self, id: u64, url: &str, user_initiated: bool) -> bool {
+        if self.current.as_ref().is_none_or(|c| c.id != id) {
+            self.finish(Some(NavigationError::Cancelled));
+        }
+        let mut args = NavigationStarting {
+            url: url.to_owned(),
+            user_initiated,
+            cancel: false,
+        };
+        if let Some(f) = &mut self.handlers.starting {
+            f(&mut args);
+        }
+        trace_event!(debug, id, url, "navigation starting");
+        let started = match &self.current {
+            Some(c) if c.id == id => c.started,
+            _ => crate::trace::now()
+        };
+        self.current = Some(Current { id, url: url.to_owned(), stage: Stage::Started, started });
+        if args.cancel {
+            self.finish(Some(NavigationError::Cancelled));
+        }
+        args.cancel
+    }
+
+    pub(crate) fn content_loading(&mut self, id: u64) {
+        self.advance(Some(id), Stage::ContentLoading);
+    }
+
+    /// `id` is `None` when the engine cannot tell which navigation the document belongs to;
+    /// the report then applies to the navigation in progress.
+    pub(crate) fn dom_content_loaded(&mut self, id: Option<u64>) {
+        self.advance(id, Stage::DomContentLoaded);
+    }
+
+    pub(crate) fn completed(&mut self, id: u64, error: Option<NavigationError>) {
+        if self.current.as_ref().is_some_and(|c| c.id == id) {
+            if error.is_none() {
+                self.advance(Some(id), Stage::DomContentLoaded);
+            }
+            self.finish(error);
+        }
+    }
+
+    pub(crate) fn history_changed(&mut self, can_go_back: bool, can_go_forward: bool) {
+        if let Some(f) = &mut self.handlers.history_changed {
+            f(can_go_back, can_go_forward);
+        }
+    }
+
+    /// Emits the events between the current stage and `stage`.
+    fn advance(&mut self, id: Option<u64>, stage: Stage) {
+        let current = match &mut self.current {
+            Some(c) if id.is_none_or(|id| id == c.id) => c,
+            _ => return
+        };
+        while current.stage < stage {
+            current.stage = match current.stage {
+                Stage::Started => {
+                    if let Some(f) = &mut self.handlers.content_loading {
+                        f(&current.url);
+                    }
+                    Stage::ContentLoading
+                }
+                _ => {
+                    if let Some(f) = &mut self.handlers.dom_content_loaded {
+                        f(&current.url);
+                    }
+                    Stage::DomContentLoaded
+                }
+            }
+        }
+    }
+
+    /// Completes the navigation in progress, if any.
+    fn finish(&mut self, error: Option<NavigationError>) {
+        if let Some(current) = self.current.take() {
+            trace_event!(
+                debug,
+                id = current.id,
+                url = %current.url,
+                error = ?error,
+                elapsed_ms = current.started.elapsed().as_millis() as u64,
+                "navigation completed"
+            );
+            if let Some(f) = &mut self.handlers.completed {
+                f(&NavigationCompleted { url: current.url, error });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the events emitted for the reports of a mock backend.
+    fn mock(cancel: &'static str) -> (Navigation, Rc<RefCell<Vec<String>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let (l1, l2, l3, l4) = (log.clone(), log.clone(), log.clone(), log.clone());
+        let handlers = NavigationHandlers {
+            starting: Some(Box::new(move |args: &mut NavigationStarting| {
+                l1.borrow_mut().push(format!("starting {} {}", args.url, args.user_initiated));
+                if args.url == cancel {
+                    args.cancel();
+                }
+            })),
+            content_loading: Some(Box::new(move |url: &str| l2.borrow_mut().push(format!("content {}", url)))),
+            dom_content_loaded: Some(Box::new(move |url: &str| l3.borrow_mut().push(format!("dom {}", url)))),
+            completed: Some(Box::new(move |c: &NavigationCompleted| {
+                l4.borrow_mut().push(format!("completed {} {:?}", c.url, c.error))
+            })),
+            history_changed: None,
+        };
+        (Navigation::new(handlers), log)
+    }
+
+    fn take(log: &Rc<RefCell<Vec<String>>>) -> Vec<String> {
+        log.borrow_mut().drain(..).collect()
+    }
+
+    #[test]
+    fn full_sequence() {
+        let (mut nav, log) = mock("");
+        assert!(!nav.starting(1, "a", true));
+        nav.content_loading(1);
+        nav.dom_content_loaded(None);
+        nav.completed(1, None);
+        assert_eq!(take(&log), ["starting a true", "content a", "dom a", "completed a None"]);
+    }
+
+    #[test]
+    fn missing_steps_are_filled_in() {
+        let (mut nav, log) = mock("");
+        nav.starting(1, "a", false);
+        nav.completed(1, None);
+        assert_eq!(take(&log), ["starting a false", "content a", "dom a", "completed a None"]);
+
+        // MSHTML style: the document reports in before anything else is known.
+        nav.starting(2, "b", false);
+        nav.dom_content_loaded(Some(2));
+        nav.completed(2, None);
+        assert_eq!(take(&log), ["starting b false", "content b", "dom b", "completed b None"]);
+    }
+
+    #[test]
+    fn late_and_duplicate_reports_are_dropped() {
+        let (mut nav, log) = mock("");
+        nav.starting(1, "a", false);
+        nav.completed(1, None);
+        take(&log);
+        nav.dom_content_loaded(None);
+        nav.content_loading(1);
+        nav.completed(1, None);
+        assert!(take(&log).is_empty());
+
+        nav.starting(2, "b", false);
+        nav.dom_content_loaded(Some(2));
+        nav.content_loading(2);
+        nav.dom_content_loaded(None);
+        assert_eq!(take(&log), ["starting b false", "content b", "dom b"]);
+    }
+
+    #[test]
+    fn cancel() {
+        let (mut nav, log) = mock("blocked");
+        assert!(nav.starting(1, "blocked", true));
+        nav.completed(1, Some(NavigationError::Cancelled));
+        assert_eq!(take(&log), ["starting blocked true", "completed blocked Some(Cancelled)"]);
+        assert!(nav.current.is_none());
+    }
+
+    #[test]
+    fn failure_skips_remaining_steps() {
+        let (mut nav, log) = mock("");
+        nav.starting(1, "a", false);
+        nav.completed(1, Some(NavigationError::HostNameNotResolved));
+        assert_eq!(take(&log), ["starting a false", "completed a Some(HostNameNotResolved)"]);
+    }
+
+    #[test]
+    fn superseded_navigation_completes_first() {
+        let (mut nav, log) = mock("");
+        nav.starting(1, "a", false);
+        nav.content_loading(1);
+        nav.starting(2, "b", true);
+        nav.completed(1, Some(NavigationError::Cancelled));
+        nav.completed(2, None);
+        assert_eq!(take(&log), [
+            "starting a false",
+            "content a",
+            "completed a Some(Cancelled)",
+            "starting b true",
+            "content b",
+            "dom b",
+            "completed b None",
+        ]);
+    }
+
+    #[test]
+    fn redirects_keep_the_navigation() {
+        let (mut nav, log) = mock("");
+        nav.starting(1, "a", false);
+        nav.starting(1, "a2", false);
+        nav.completed(1, None);
+        assert_eq!(take(&log), ["starting a false", "starting a2 false", "content a2", "dom a2", "completed a2 None"]);
+    }
+}
@@ -0,0 +1,377 @@
+//! Declarative navigation allowlist.
+//!
+//! A [`NavigationPolicy`] lists the URLs the webview may load. Anything else, including
+//! `window.open` and links opening a new window, is blocked, handed to the system browser or
+//! decided by a callback.
+//!
+//! Patterns come in two forms:
+//!
+//! * `scheme://host[:port][/path]` matches URLs of that origin. The host may start with `*.`
+//!   to match any subdomain, and `*` on its own matches any host; a `*` scheme matches `http`
+//!   and `https`. Without a path every URL of the origin matches. A path is a glob over the
+//!   path and query of the URL: `*` matches any run of characters, `?` a single one.
+//! * Patterns without an authority, like `about:blank`, are globs over the whole URL
+//!   (without fragment).
+//!
+//! Invalid patterns, e.g. with a `*` inside a host label or in the port, match nothing and
+//! are logged.
+
+/// What happens to a navigation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PolicyDecision {
+    Allow,
+    Block,
+    /// Block and open the URL in the system browser. Only `http`, `https` and `mailto` URLs
+    /// are handed over; others are just blocked.
+    OpenExternally,
+}
+
+/// A navigation the allowlist does not cover, passed to [`NavigationPolicy::otherwise_ask()`].
+#[derive(Debug)]
+pub struct PolicyRequest<'r> {
+    pub url: &'r str,
+    /// `window.open` or a link targeting a new window.
+    pub new_window: bool,
+    pub user_initiated: bool,
+}
+
+pub type AskHandler = Box<dyn FnMut(&PolicyRequest) -> PolicyDecision>;
+
+enum Otherwise {
+    Decide(PolicyDecision),
+    Ask(AskHandler),
+}
+
+/// Allowlist for navigations and new windows, see the [module documentation](index.html).
+pub struct NavigationPolicy {
+    allowed: Vec<Pattern>,
+    otherwise: Otherwise,
+}
+
+impl Default for NavigationPolicy {
+    fn default() -> Self {
+        NavigationPolicy {
+            allowed: Vec::new(),
+            otherwise: Otherwise::Decide(PolicyDecision::Block),
+        }
+    }
+}
+
+impl NavigationPolicy {
+    /// A policy that blocks everything but the application's own content.
+    pub fn new() -> Self {
+        NavigationPolicy::default()
+    }
+
+    /// Allows the URLs matching `pattern`. Invalid patterns match nothing.
+    pub fn allow(mut self, pattern: &str) -> Self {
+        match Pattern::parse(pattern) {
+            Some(pattern) => self.allowed.push(pattern),
+            None => log::warn!("invalid navigation pattern {:?}, it allows nothing", pattern)
+        }
+        self
+    }
+
+    /// Blocks everything that is not allowed. This is the default.
+    pub fn otherwise_block(mut self) -> Self {
+        self.otherwise = Otherwise::Decide(PolicyDecision::Block);
+        self
+    }
+
+    /// Opens everything that is not allowed in the system browser.
+    pub fn otherwise_open_externally(mut self) -> Self {
+        self.otherwise = Otherwise::Decide(PolicyDecision::OpenExternally);
+        self
+    }
+
+    /// Lets `f` decide about everything that is not allowed.
+    pub fn otherwise_ask<F: FnMut(&PolicyRequest) -> PolicyDecision + 'static>(mut self, f: F) -> Self {
+        self.otherwise = Otherwise::Ask(Box::new(f));
+        self
+    }
+
+    /// Whether `url` matches one of the allowed patterns.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        self.allowed.iter().any(|p| p.matches(url))
+    }
+
+    /// A policy with the same allowlist that blocks everything else.
+    pub(crate) fn allowlist(&self) -> NavigationPolicy {
+        NavigationPolicy { allowed: self.allowed.clone(), ..NavigationPolicy::default() }
+    }
+
+    pub fn decide(&mut self, request: &PolicyRequest) -> PolicyDecision {
+        if self.is_allowed(request.url) {
+            return PolicyDecision::Allow
+        }
+        let decision = match &mut self.otherwise {
+            Otherwise::Decide(decision) => *decision,
+            Otherwise::Ask(f) => f(request),
+        };
+        if decision == PolicyDecision::OpenExternally && !is_external(request.url) {
+            return PolicyDecision::Block
+        }
+        decision
+    }
+}
+
+/// Whether `url` may be handed to the system.
+pub(crate) fn is_external(url: &str) -> bool {
+    let scheme = url.split(':').next().unwrap_or("").to_ascii_lowercase();
+    url.contains(':') && ["http", "https", "mailto"].contains(&scheme.as_str())
+}
+
+/// The parts of a URL that patterns match on.
+#[derive(Debug, PartialEq, Eq)]
+struct Url {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+}
+
+impl Url {
+    /// Parses `scheme://[userinfo@]host[:port]`, ignoring the rest.
+    fn parse(url: &str) -> Option<Url> {
+        let (scheme, rest) = url.split_once("://")?;
+        if !is_scheme(scheme) {
+            return None
+        }
+        let (host, port) = authority(rest)?;
+        let scheme = scheme.to_ascii_lowercase();
+        let port = port.or(default_port(&scheme));
+        Some(Url { scheme, host, port })
+    }
+}
+
+fn is_scheme(scheme: &str) -> bool {
+    !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        _ => None
+    }
+}
+
+/// Host and explicit port of the authority at the start of `rest`, the part after `://`.
+fn authority(rest: &str) -> Option<(String, Option<u16>)> {
+    let authority = rest.split(['/', '?', '#', '\\']).next().unwrap_or("");
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    let (host, port) = match host_port.rsplit_once(':') {
+        // Not the colons of an IPv6 address.
+        Some((host, port)) if !port.contains(']') => (host, Some(port.parse().ok()?)),
+        _ => (host_port, None)
+    };
+    Some((host.trim_end_matches('.').to_ascii_lowercase(), port))
+}
+
+/// Path and query of a URL with an authority, `/` if it has no path.
+fn path_and_query(url: &str) -> String {
+    let rest = url.split_once("://").map_or("", |(_, rest)| rest);
+    let rest = rest.split('#').next().unwrap_or("");
+    let path = rest.find(['/', '?', '\\']).map_or("", |i| &rest[i..]);
+    // Browsers read backslashes in http(s) URLs as slashes.
+    let path = path.replace('\\', "/");
+    if path.starts_with('/') { path } else { format!("/{}", path) }
+}
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    /// `scheme://host[:port][/path]`. `port` is `None` for the scheme's default port.
+    Url { scheme: String, host: String, port: Option<u16>, path: Option<String> },
+    /// A pattern without an authority, e.g. `about:blank`.
+    Glob(String),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Option<Pattern> {
+        let (scheme, rest) = match pattern.split_once("://") {
+            Some(parts) => parts,
+            None => return Some(Pattern::Glob(pattern.to_owned()))
+        };
+        let scheme = scheme.to_ascii_lowercase();
+        if scheme != "*" && !is_scheme(&scheme) {
+            return None
+        }
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (host, port) = authority(&rest[..end])?;
+        // `*` only stands for the whole host or for leading labels.
+        if host.is_empty() || (host != "*" && host.strip_prefix("*.").unwrap_or(&host).contains('*')) {
+            return None
+        }
+        let path = rest[end..].split('#').next().unwrap_or("");
+        let path = if path.is_empty() || path == "/" { None } else { Some(path.to_owned()) };
+        Some(Pattern::Url { scheme, host, port, path })
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        match self {
+            Pattern::Url { scheme, host, port, path } => {
+                let parsed = match Url::parse(url) {
+                    Some(url) => url,
+                    None => return false
+                };
+                let scheme_matches = match scheme.as_str() {
+                    "*" => parsed.scheme == "http" || parsed.scheme == "https",
+                    scheme => scheme == parsed.scheme
+                };
+                let host_matches = match host.strip_prefix("*.") {
+                    Some(domain) => parsed.host.strip_suffix(domain).is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                    None => host == "*" || *host == parsed.host
+                };
+                scheme_matches
+                    && host_matches
+                    && parsed.port == port.or(default_port(&parsed.scheme))
+                    && path.as_ref().is_none_or(|path| glob(path, &path_and_query(url)))
+            }
+            Pattern::Glob(pattern) => {
+                let url = url.split('#').next().unwrap_or("");
+                glob(pattern, url)
+            }
+        }
+    }
+}
+
+/// Matches `text` against a glob with `*` and `?`.
+fn glob(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut star = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nav(url: &str) -> PolicyRequest<'_> {
+        PolicyRequest { url, new_window: false, user_initiated: true }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("a*c", "abbbc"));
+        assert!(glob("a?c", "abc"));
+        assert!(glob("*", ""));
+        assert!(!glob("a*c", "abcd"));
+        assert!(glob("https://x.com/docs/*", "https://x.com/docs/a/b"));
+        assert!(!glob("https://x.com/docs/*", "https://x.com/other"));
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            Url::parse("HTTPS://User:pw@Example.COM./a?b"),
+            Some(Url { scheme: "https".into(), host: "example.com".into(), port: Some(443) })
+        );
+        assert_eq!(Url::parse("http://[::1]:8080/").map(|u| (u.host, u.port)), Some(("[::1]".into(), Some(8080))));
+        assert_eq!(Url::parse("http://[::1]/").map(|u| (u.host, u.port)), Some(("[::1]".into(), Some(80))));
+        assert_eq!(Url::parse("about:blank"), None);
+        assert_eq!(Url::parse("http://host:99999/"), None);
+    }
+
+    #[test]
+    fn origins() {
+        let p = NavigationPolicy::new().allow("https://example.com").allow("http://localhost:5173");
+        assert!(p.is_allowed("https://example.com/"));
+        assert!(p.is_allowed("https://example.com:443/a/b?c#d"));
+        assert!(p.is_allowed("https://EXAMPLE.com"));
+        assert!(!p.is_allowed("http://example.com/"));
+        assert!(!p.is_allowed("https://example.com:8443/"));
+        assert!(!p.is_allowed("https://example.com.evil.com/"));
+        assert!(!p.is_allowed("https://example.com@evil.com/"));
+        assert!(!p.is_allowed("https://evil.com/?https://example.com"));
+        assert!(!p.is_allowed("https://evil.com\\@example.com/"));
+        assert!(p.is_allowed("http://localhost:5173/src/main.ts"));
+        assert!(!p.is_allowed("http://localhost/"));
+    }
+
+    #[test]
+    fn wildcard_hosts() {
+        let p = NavigationPolicy::new().allow("https://*.example.com").allow("app://*");
+        assert!(p.is_allowed("https://a.example.com/"));
+        assert!(p.is_allowed("https://a.b.example.com/"));
+        assert!(!p.is_allowed("https://example.com/"));
+        assert!(!p.is_allowed("https://badexample.com/"));
+        assert!(p.is_allowed("app://anything/x"));
+    }
+
+    #[test]
+    fn wildcards_do_not_cross_url_parts() {
+        let p = NavigationPolicy::new().allow("https://*.example.com/*");
+        assert!(p.is_allowed("https://a.example.com/x?y"));
+        assert!(p.is_allowed("https://a.example.com"));
+        assert!(!p.is_allowed("https://evil.com/.example.com/x"));
+        assert!(!p.is_allowed("https://evil.com?.example.com/"));
+        assert!(!p.is_allowed("https://evil.com#.example.com/"));
+        assert!(!p.is_allowed("https://evil.com\\.example.com/"));
+        assert!(!p.is_allowed("https://a.example.com.evil.com/"));
+        assert!(!p.is_allowed("https://a.example.com@evil.com/"));
+
+        let p = NavigationPolicy::new().allow("https://docs.rs/webview-x/*");
+        assert!(p.is_allowed("https://docs.rs/webview-x/a?b"));
+        assert!(!p.is_allowed("https://evil.com/?https://docs.rs/webview-x/"));
+        assert!(!p.is_allowed("https://docs.rs.evil.com/webview-x/"));
+        assert!(!p.is_allowed("https://docs.rs:8443/webview-x/"));
+    }
+
+    #[test]
+    fn scheme_wildcard_and_invalid_patterns() {
+        let p = NavigationPolicy::new().allow("*://example.com");
+        assert!(p.is_allowed("http://example.com/"));
+        assert!(p.is_allowed("https://example.com/"));
+        assert!(!p.is_allowed("ftp://example.com/"));
+        assert!(!p.is_allowed("http://example.com:8080/"));
+
+        for pattern in &["https://a*b.com", "https://*example.com", "https://example.*", "https://x.com:*", "ht*p://x.com", "https://"] {
+            assert!(Pattern::parse(pattern).is_none(), "{}", pattern);
+        }
+        let p = NavigationPolicy::new().allow("https://a.*.com/*");
+        assert!(!p.is_allowed("https://a.b.com/"));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        let p = NavigationPolicy::new().allow("https://docs.rs/webview-x/*").allow("about:blank");
+        assert!(p.is_allowed("https://docs.rs/webview-x/latest#section"));
+        assert!(!p.is_allowed("https://docs.rs/other"));
+        assert!(p.is_allowed("about:blank"));
+        assert!(!p.is_allowed("about:blankx"));
+    }
+
+    #[test]
+    fn decisions() {
+        let mut p = NavigationPolicy::new().allow("https://example.com");
+        assert_eq!(p.decide(&nav("https://example.com/a")), PolicyDecision::Allow);
+        assert_eq!(p.decide(&nav("https://evil.com/")), PolicyDecision::Block);
+
+        let mut p = p.otherwise_open_externally();
+        assert_eq!(p.decide(&nav("https://evil.com/")), PolicyDecision::OpenExternally);
+        assert_eq!(p.decide(&nav("mailto:a@b.c")), PolicyDecision::OpenExternally);
+        assert_eq!(p.decide(&nav("file:///C:/Windows/")), PolicyDecision::Block);
+        assert_eq!(p.decide(&nav("ms-settings:privacy")), PolicyDecision::Block);
+
+        let mut p = p.otherwise_ask(|r| if r.new_window { PolicyDecision::OpenExternally } else { PolicyDecision::Block });
+        assert_eq!(p.decide(&nav("https://evil.com/")), PolicyDecision::Block);
+        let popup = PolicyRequest { new_window: true, ..nav("https://other.com/") };
+        assert_eq!(p.decide(&popup), PolicyDecision::OpenExternally);
+        assert_eq!(p.decide(&nav("https://example.com/")), PolicyDecision::Allow);
+    }
+}
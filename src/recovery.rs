@@ -0,0 +1,246 @@
+//! Recovering from crashed or hung browser processes.
+//!
+//! WebView2 runs the page in separate processes. When one of them fails, the webview reports
+//! it through [`on_process_failed`](../struct.WebViewBuilder.html#method.on_process_failed)
+//! and [`Recovery`] decides, following the [`RecoveryPolicy`], how to bring the page back.
+//! Failures in quick succession are retried with exponential backoff until the attempts run
+//! out; after a quiet period the count starts over.
+//!
+//! MSHTML renders in the application's own process, so there is nothing to recover there.
+use std::time::{Duration, Instant};
+
+/// Which process failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProcessFailure {
+    /// The browser process exited. The webview is closed and has to be recreated.
+    BrowserExited,
+    /// The main frame's renderer exited. The engine shows its own error page.
+    RendererExited,
+    /// The main frame's renderer stopped responding.
+    RendererUnresponsive,
+    /// A renderer of some frames exited. Only those frames show an error page.
+    FrameRendererExited,
+    /// A GPU, utility or other helper process exited. The engine restarts it.
+    HelperExited,
+}
+
+impl ProcessFailure {
+    /// Whether the main frame is gone and needs to be recovered.
+    pub fn affects_page(self) -> bool {
+        matches!(self, ProcessFailure::BrowserExited | ProcessFailure::RendererExited | ProcessFailure::RendererUnresponsive)
+    }
+}
+
+pub type ProcessFailedHandler = Box<dyn FnMut(ProcessFailure)>;
+
+/// How to bring back a page whose process failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// Reload the page. A failed browser process always recreates the controller instead.
+    Reload,
+    /// Close the controller and create a new one, then load the last page again.
+    RecreateController,
+    /// Show the policy's error page. Without one, [`Reload`](#variant.Reload) is used instead.
+    ErrorPage,
+    /// Leave it to the application's `on_process_failed` callback.
+    Nothing,
+}
+
+/// When and how to recover from process failures.
+#[derive(Clone, Debug)]
+pub struct RecoveryPolicy {
+    pub action: RecoveryAction,
+    /// Attempts before giving up and showing the error page, if any.
+    pub max_attempts: u32,
+    /// Delay before the first attempt, doubled for every further one.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Time without failures after which the attempts start over.
+    pub reset_after: Duration,
+    /// HTML shown by [`RecoveryAction::ErrorPage`] and when the attempts run out.
+    pub error_page: Option<String>,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        RecoveryPolicy {
+            action: RecoveryAction::Reload,
+            max_attempts: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            reset_after: Duration::from_secs(60),
+            error_page: None,
+        }
+    }
+}
+
+impl RecoveryPolicy {
+    /// Reloads the page up to 3 times, waiting 0.5, 1 and 2 seconds.
+    pub fn new() -> Self {
+        RecoveryPolicy::default()
+    }
+
+    pub fn action(mut self, action: RecoveryAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Waits `initial` before the first attempt and doubles the delay up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = reset_after;
+        self
+    }
+
+    pub fn error_page(mut self, html: &str) -> Self {
+        self.error_page = Some(html.to_owned());
+        self
+    }
+}
+
+/// What the engine does about a failure.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    Reload,
+    RecreateController,
+    ErrorPage,
+}
+
+/// Tracks the attempts, see the [module documentation](index.html).
+pub(crate) struct Recovery {
+    policy: RecoveryPolicy,
+    attempts: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Recovery {
+    pub(crate) fn new(mut policy: RecoveryPolicy) -> Self {
+        if policy.action == RecoveryAction::ErrorPage && policy.error_page.is_none() {
+            log::warn!("RecoveryAction::ErrorPage without an error page, reloading instead");
+            policy.action = RecoveryAction::Reload;
+        }
+        Recovery { policy, attempts: 0, last_failure: None }
+    }
+
+    pub(crate) fn error_page(&self) -> Option<&str> {
+        self.policy.error_page.as_deref()
+    }
+
+    /// What to do about `failure` at `now`, and after which delay. `None` when there is
+    /// nothing to do or the attempts ran out without an error page to show.
+    pub(crate) fn failed(&mut self, failure: ProcessFailure, now: Instant) -> Option<(Step, Duration)> {
+        if !failure.affects_page() {
+            return None
+        }
+        if self.last_failure.is_some_and(|t| now.saturating_duration_since(t) >= self.policy.reset_after) {
+            self.attempts = 0;
+        }
+        self.last_failure = Some(now);
+        let error_page = self.policy.error_page.is_some();
+        let step = match self.policy.action {
+            RecoveryAction::Nothing => return None,
+            RecoveryAction::ErrorPage => return error_page.then_some((Step::ErrorPage, Duration::ZERO)),
+            _ if failure == ProcessFailure::BrowserExited => Step::RecreateController,
+            RecoveryAction::Reload => Step::Reload,
+            _ => Step::RecreateController,
+        };
+        if self.attempts >= self.policy.max_attempts {
+            // Without a browser process there is nothing to show the page in.
+            return (error_page && failure != ProcessFailure::BrowserExited).then_some((Step::ErrorPage, Duration::ZERO))
+        }
+        let delay = self.policy.backoff.saturating_mul(1 << self.attempts.min(16)).min(self.policy.max_backoff);
+        self.attempts += 1;
+        Some((step, delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn run(recovery: &mut Recovery, failures: &[(ProcessFailure, u32)]) -> Vec<Option<(Step, Duration)>> {
+        let start = Instant::now();
+        failures.iter().map(|&(f, at)| recovery.failed(f, start + at * MS)).collect()
+    }
+
+    #[test]
+    fn backoff_until_attempts_run_out() {
+        let mut r = Recovery::new(RecoveryPolicy::new().backoff(100 * MS, 300 * MS).max_attempts(3));
+        let f = ProcessFailure::RendererExited;
+        assert_eq!(run(&mut r, &[(f, 0), (f, 200), (f, 500), (f, 900)]), [
+            Some((Step::Reload, 100 * MS)),
+            Some((Step::Reload, 200 * MS)),
+            Some((Step::Reload, 300 * MS)),
+            None,
+        ]);
+    }
+
+    #[test]
+    fn error_page_when_giving_up() {
+        let mut r = Recovery::new(RecoveryPolicy::new().max_attempts(1).error_page("<p>Sorry</p>"));
+        let f = ProcessFailure::RendererUnresponsive;
+        assert_eq!(run(&mut r, &[(f, 0), (f, 10)]), [
+            Some((Step::Reload, 500 * MS)),
+            Some((Step::ErrorPage, Duration::ZERO)),
+        ]);
+        assert_eq!(r.error_page(), Some("<p>Sorry</p>"));
+    }
+
+    #[test]
+    fn attempts_start_over_after_quiet_period() {
+        let mut r = Recovery::new(RecoveryPolicy::new().max_attempts(1).reset_after(1000 * MS));
+        let f = ProcessFailure::RendererExited;
+        assert_eq!(run(&mut r, &[(f, 0), (f, 999), (f, 2000)]), [
+            Some((Step::Reload, 500 * MS)),
+            None,
+            Some((Step::Reload, 500 * MS)),
+        ]);
+    }
+
+    #[test]
+    fn browser_failures_recreate_the_controller() {
+        let mut r = Recovery::new(RecoveryPolicy::new().max_attempts(1).error_page("x"));
+        let f = ProcessFailure::BrowserExited;
+        assert_eq!(run(&mut r, &[(f, 0), (f, 10)]), [Some((Step::RecreateController, 500 * MS)), None]);
+    }
+
+    #[test]
+    fn actions() {
+        let f = ProcessFailure::RendererExited;
+        let mut r = Recovery::new(RecoveryPolicy::new().action(RecoveryAction::RecreateController));
+        assert_eq!(run(&mut r, &[(f, 0)]), [Some((Step::RecreateController, 500 * MS))]);
+        let mut r = Recovery::new(RecoveryPolicy::new().action(RecoveryAction::ErrorPage).error_page("x"));
+        assert_eq!(run(&mut r, &[(f, 0), (f, 1)]), [Some((Step::ErrorPage, Duration::ZERO)); 2]);
+        let mut r = Recovery::new(RecoveryPolicy::new().action(RecoveryAction::Nothing));
+        assert_eq!(run(&mut r, &[(f, 0)]), [None]);
+    }
+
+    #[test]
+    fn error_page_action_without_a_page_reloads() {
+        let mut r = Recovery::new(RecoveryPolicy::new().action(RecoveryAction::ErrorPage).max_attempts(1));
+        let f = ProcessFailure::RendererExited;
+        assert_eq!(run(&mut r, &[(f, 0), (f, 10)]), [Some((Step::Reload, 500 * MS)), None]);
+        let f = ProcessFailure::BrowserExited;
+        let mut r = Recovery::new(RecoveryPolicy::new().action(RecoveryAction::ErrorPage));
+        assert_eq!(run(&mut r, &[(f, 0)]), [Some((Step::RecreateController, 500 * MS))]);
+    }
+
+    #[test]
+    fn other_processes_are_left_to_the_engine() {
+        let mut r = Recovery::new(RecoveryPolicy::new());
+        let failures = [(ProcessFailure::FrameRendererExited, 0), (ProcessFailure::HelperExited, 1)];
+        assert_eq!(run(&mut r, &failures), [None, None]);
+    }
+}
@@ -0,0 +1,202 @@
+//! Running JavaScript from Rust.
+//!
+//! Both engines run the same wrapper: it evaluates the script in the global scope, catches
+//! exceptions and serializes the outcome with `JSON.stringify`. WebView2 returns the text
+//! from `ExecuteScript`; MSHTML cannot return values, so the wrapper posts it back through
+//! the bridge as `eval:<id>:<json>` and the id finds the callback. The page can post to the
+//! bridge too, so ids are random and replies to ids nobody waits for are dropped.
+//!
+//! Init scripts, which run at the start of every document, are kept here too.
+use std::fmt::{self, Display, Formatter};
+use serde_json::Value;
+use crate::bridge;
+
+/// A JavaScript exception thrown by an evaluated script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsException {
+    /// `Error`, `TypeError`, ... or the type of a non-error value that was thrown.
+    pub name: String,
+    pub message: String,
+    /// Only where the engine provides stacks.
+    pub stack: Option<String>,
+}
+
+/// Why [`WebView::eval()`](../enum.WebView.html#method.eval) did not produce a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EvalError {
+    /// The script threw.
+    Exception(JsException),
+    /// The webview is still being created.
+    NotReady,
+    /// The document was replaced before the script answered.
+    Cancelled,
+    /// The engine failed to run the script or returned something unexpected.
+    Engine(String),
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Exception(e) => write!(f, "{}: {}", e.name, e.message),
+            EvalError::NotReady => f.write_str("the webview is not ready"),
+            EvalError::Cancelled => f.write_str("the document was replaced before the script answered"),
+            EvalError::Engine(e) => write!(f, "script evaluation failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+pub type EvalResult = Result<Value, EvalError>;
+pub type EvalCallback = Box<dyn FnOnce(EvalResult)>;
+
+/// Identifies an init script, see
+/// [`WebView::add_init_script()`](../enum.WebView.html#method.add_init_script).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ScriptId(u64);
+
+/// Init scripts in the order they run.
+#[derive(Default)]
+pub(crate) struct InitScripts {
+    scripts: Vec<(ScriptId, String)>,
+    next: u64,
+}
+
+impl InitScripts {
+    /// Adds `js`, wrapped so it only runs in the top-level document if `main_frame_only`.
+    pub(crate) fn add(&mut self, js: &str, main_frame_only: bool) -> ScriptId {
+        let id = ScriptId(self.next);
+        self.next += 1;
+        let js = if main_frame_only { main_frame(js) } else { js.to_owned() };
+        self.scripts.push((id, js));
+        id
+    }
+
+    /// Returns `false` if there is no script `id`.
+    pub(crate) fn remove(&mut self, id: ScriptId) -> bool {
+        let before = self.scripts.len();
+        self.scripts.retain(|(i, _)| *i != id);
+        self.scripts.len() != before
+    }
+
+    pub(crate) fn get(&self, id: ScriptId) -> Option<&str> {
+        self.scripts.iter().find(|(i, _)| *i == id).map(|(_, js)| js.as_str())
+    }
+
+    pub(crate) fn ids(&self) -> Vec<ScriptId> {
+        self.scripts.iter().map(|(id, _)| *id).collect()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (ScriptId, &str)> {
+        self.scripts.iter().map(|(id, js)| (*id, js.as_str()))
+    }
+}
+
+/// Runs `js` in the global scope of the top-level document only.
+fn main_frame(js: &str) -> String {
+    format!("if (window.top === window) (0, eval)({});", bridge::js_string(js))
+}
+
+/// Expression that evaluates `js` and yields the outcome as JSON text.
+pub(crate) fn serialized(js: &str) -> String {
+    format!(r#"(function () {{
+  var r;
+  try {{
+    r = {{ ok: (0, eval)({src}) }};
+  }} catch (e) {{
+    r = {{ error: {{
+      name: String(e && e.name || typeof e),
+      message: String(e && e.message !== undefined ? e.message : e),
+      stack: e && e.stack ? String(e.stack) : null
+    }} }};
+  }}
+  if (!window.JSON) return '{{"error":{{"name":"Error","message":"JSON is not available in this document mode"}}}}';
+  try {{
+    return JSON.stringify(r);
+  }} catch (e) {{
+    return JSON.stringify({{ error: {{ name: 'TypeError', message: 'the result cannot be serialized: ' + e.message }} }});
+  }}
+}})()"#, src = bridge::js_string(js))
+}
+
+/// Statement that evaluates `js` and posts the outcome back as `eval:<id>:<json>`.
+pub(crate) fn posted(js: &str, id: &str) -> String {
+    format!("window.external.invoke('{}eval:{}:' + {});", bridge::PREFIX, id, serialized(js))
+}
+
+/// Parses a bridge reply `<id>:<json>`, where the id is hex encoded.
+pub(crate) fn parse_posted(msg: &str) -> Option<(&str, EvalResult)> {
+    let (id, json) = msg.split_once(':')?;
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None
+    }
+    Some((id, parse(json)))
+}
+
+/// Parses the JSON text produced by [`serialized()`].
+pub(crate) fn parse(json: &str) -> EvalResult {
+    let outcome: Value = serde_json::from_str(json).map_err(|e| EvalError::Engine(e.to_string()))?;
+    if let Some(error) = outcome.get("error") {
+        let field = |name: &str| error.get(name).and_then(Value::as_str).map(str::to_owned);
+        return Err(EvalError::Exception(JsException {
+            name: field("name").unwrap_or_else(|| "Error".to_owned()),
+            message: field("message").unwrap_or_default(),
+            stack: field("stack"),
+        }))
+    }
+    Ok(outcome.get("ok").cloned().unwrap_or(Value::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn values() {
+        assert_eq!(parse(r#"{"ok":{"a":[1,2]}}"#), Ok(json!({"a": [1, 2]})));
+        // `undefined` is dropped by JSON.stringify.
+        assert_eq!(parse("{}"), Ok(Value::Null));
+    }
+
+    #[test]
+    fn exceptions() {
+        let err = parse(r#"{"error":{"name":"TypeError","message":"x is not a function","stack":"at <anonymous>:1:1"}}"#);
+        assert_eq!(err, Err(EvalError::Exception(JsException {
+            name: "TypeError".to_owned(),
+            message: "x is not a function".to_owned(),
+            stack: Some("at <anonymous>:1:1".to_owned()),
+        })));
+        let err = parse(r#"{"error":{"name":"string","message":"thrown","stack":null}}"#).unwrap_err();
+        assert_eq!(err.to_string(), "string: thrown");
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(matches!(parse("null"), Ok(Value::Null)));
+        assert!(matches!(parse("not json"), Err(EvalError::Engine(_))));
+    }
+
+    #[test]
+    fn init_scripts() {
+        let mut scripts = InitScripts::default();
+        let a = scripts.add("a()", false);
+        let b = scripts.add("b()", true);
+        let c = scripts.add("c()", false);
+        assert!(scripts.remove(b));
+        assert!(!scripts.remove(b));
+        assert_eq!(scripts.ids(), [a, c]);
+        assert_eq!(scripts.get(b), None);
+        let d = scripts.add("d()", false);
+        assert_ne!(d, b);
+        assert_eq!(scripts.iter().map(|(_, js)| js).collect::<Vec<_>>(), ["a()", "c()", "d()"]);
+        assert_eq!(main_frame(r#"x = "1""#), r#"if (window.top === window) (0, eval)("x = \"1\"");"#);
+    }
+
+    #[test]
+    fn posted_replies() {
+        let (id, result) = parse_posted(r#"4f2a:{"ok":"a:b"}"#).unwrap();
+        assert_eq!((id, result), ("4f2a", Ok(json!("a:b"))));
+        assert!(parse_posted("x:{}").is_none());
+    }
+}
@@ -0,0 +1,294 @@
+//! Loopback HTTP server for engines that cannot serve a custom scheme (MSHTML).
+//!
+//! Binds `127.0.0.1` on a random port and serves an [`AssetResolver`] through
+//! [`assets::serve()`]. Every request must carry the per-session token, either in the
+//! `__webviewx_token` query parameter of the start URL or in the cookie set in response to
+//! it, so other local processes cannot read the application's assets. Cookies are shared by
+//! all ports of a host, so the cookie name includes the port. The response to the token in
+//! the query redirects to the URL without it, which keeps the token out of the history, and
+//! no response sends a `Referer`. Requests must also name the server in their `Host` header,
+//! which keeps DNS rebinding pages out.
+//!
+//! Each connection gets a thread, up to [`MAX_CONNECTIONS`]; further ones are closed.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crate::assets::{self, AssetResolver, Response};
+
+const TOKEN_PARAM: &str = "__webviewx_token";
+const COOKIE: &str = "webviewx_token";
+const MAX_HEADER: usize = 16 * 1024;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_CONNECTIONS: usize = 16;
+
+pub(crate) struct AssetServer {
+    addr: SocketAddr,
+    token: Arc<str>,
+    stop: Arc<AtomicBool>,
+}
+
+impl AssetServer {
+    pub(crate) fn start(resolver: Arc<dyn AssetResolver>) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let token: Arc<str> = Arc::from(new_token()?);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (t, s) = (token.clone(), stop.clone());
+        let active = Arc::new(AtomicUsize::new(0));
+        thread::Builder::new().name("webviewx-assets".to_owned()).spawn(move || {
+            for stream in listener.incoming() {
+                if s.load(Ordering::SeqCst) {
+                    break
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                // Any local process can connect, so dropping `stream` closes what is over the cap.
+                if active.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    continue
+                }
+                let slot = Slot::take(&active);
+                let (resolver, token) = (resolver.clone(), t.clone());
+                let _ = thread::Builder::new().spawn(move || {
+                    let _slot = slot;
+                    connection(stream, &*resolver, &token, addr.port())
+                });
+            }
+        })?;
+        Ok(AssetServer { addr, token, stop })
+    }
+
+    /// `http://127.0.0.1:<port>`.
+    pub(crate) fn origin(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// URL of `path` that also authorizes the session.
+    pub(crate) fn url(&self, path: &str) -> String {
+        let (path, fragment) = match path.find('#') {
+            Some(i) => path.split_at(i),
+            None => (path, "")
+        };
+        let sep = if path.contains('?') { '&' } else { '?' };
+        format!("http://{}/{}{}{}={}{}", self.addr, path.trim_start_matches('/'), sep, TOKEN_PARAM, self.token, fragment)
+    }
+}
+
+impl Drop for AssetServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the accept loop.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// One of the [`MAX_CONNECTIONS`] connections, given back on drop.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(active: &Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        Slot(active.clone())
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 128 bits from the system's secure random generator, hex encoded.
+pub(crate) fn new_token() -> io::Result<String> {
+    use winapi::um::ntsecapi::RtlGenRandom;
+    let mut buf = [0u8; 16];
+    if unsafe { RtlGenRandom(buf.as_mut_ptr() as *mut _, buf.len() as u32) } == 0 {
+        return Err(io::Error::other("RtlGenRandom failed"))
+    }
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Compares without leaking the position of the first difference.
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Serves requests on one keep-alive connection.
+fn connection(stream: TcpStream, resolver: &dyn AssetResolver, token: &str, port: u16) {
+    let _ = stream.set_read_timeout(Some(IDLE_TIMEOUT));
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(_) => return
+    };
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(head)) = read_head(&mut reader) {
+        let (res, keep_alive) = handle(&head, resolver, token, port);
+        if write_response(&mut writer, &res, keep_alive).is_err() || !keep_alive {
+            break
+        }
+    }
+}
+
+/// Reads the request line and headers. `None` on a closed connection.
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<String>>> {
+    let mut lines = Vec::new();
+    let mut size = 0;
+    loop {
+        let mut line = String::new();
+        let n = reader.by_ref().take((MAX_HEADER - size) as u64 + 1).read_line(&mut line)?;
+        size += n;
+        if n == 0 {
+            return Ok(None)
+        }
+        if size > MAX_HEADER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request header too large"))
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            if lines.is_empty() {
+                continue
+            }
+            return Ok(Some(lines))
+        }
+        lines.push(line.to_owned());
+    }
+}
+
+/// Returns the response and whether the connection can be kept open.
+fn handle(head: &[String], resolver: &dyn AssetResolver, token: &str, port: u16) -> (Response, bool) {
+    let mut parts = head[0].split(' ');
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return (Response::status(400, "Bad Request"), false)
+    };
+    let header = |name: &str| head[1..].iter().find_map(|h| {
+        let (n, v) = h.split_once(':')?;
+        if n.trim().eq_ignore_ascii_case(name) { Some(v.trim()) } else { None }
+    });
+    // Bodies are never expected; rather than skipping one, close the connection.
+    let keep_alive = header("Content-Length").is_none_or(|l| l == "0")
+        && header("Transfer-Encoding").is_none()
+        && !header("Connection").is_some_and(|c| c.eq_ignore_ascii_case("close"));
+
+    if header("Host") != Some(&*format!("{}:{}", Ipv4Addr::LOCALHOST, port)) {
+        return (Response::status(403, "Forbidden"), keep_alive)
+    }
+    let cookie = format!("{}_{}", COOKIE, port);
+    let query = target.split_once('?').map_or("", |(_, q)| q);
+    let from_query = query.split('&').find_map(|p| p.strip_prefix(TOKEN_PARAM)?.strip_prefix('='));
+    let from_cookie = header("Cookie").and_then(|c| {
+        c.split(';').find_map(|p| p.trim().strip_prefix(&*cookie)?.strip_prefix('='))
+    });
+    if !from_query.or(from_cookie).is_some_and(|t| token_eq(t, token)) {
+        return (Response::status(403, "Forbidden"), keep_alive)
+    }
+
+    let mut res = if from_query.is_some() {
+        let mut res = Response::status(303, "See Other");
+        res.header("Location", without_token(target));
+        res.header("Set-Cookie", format!("{}={}; Path=/; HttpOnly; SameSite=Strict", cookie, token));
+        res
+    } else {
+        let path = assets::request_path(target, "");
+        assets::serve(resolver, &assets::Request {
+            method,
+            path: &path,
+            range: header("Range"),
+            if_none_match: header("If-None-Match"),
+            accept_encoding: header("Accept-Encoding"),
+        })
+    };
+    res.header("Referrer-Policy", "no-referrer".to_owned());
+    (res, keep_alive)
+}
+
+/// `target` without the token parameter.
+fn without_token(target: &str) -> String {
+    let (path, query) = match target.split_once('?') {
+        Some(parts) => parts,
+        None => return target.to_owned()
+    };
+    let query: Vec<&str> = query.split('&').filter(|p| p.split('=').next() != Some(TOKEN_PARAM)).collect();
+    if query.is_empty() { path.to_owned() } else { format!("{}?{}", path, query.join("&")) }
+}
+
+fn write_response<W: Write>(w: &mut W, res: &Response, keep_alive: bool) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", res.status, res.reason);
+    if !res.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("Content-Length")) {
+        head.push_str(&format!("Content-Length: {}\r\n", res.body.len()));
+    }
+    for (name, value) in &res.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(if keep_alive { "Connection: keep-alive\r\n\r\n" } else { "Connection: close\r\n\r\n" });
+    w.write_all(head.as_bytes())?;
+    w.write_all(&res.body)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::MemoryAssets;
+
+    fn get(port: u16, target: &str, cookie: Option<&str>) -> Vec<String> {
+        let mut head = vec![format!("GET {} HTTP/1.1", target), format!("Host: 127.0.0.1:{}", port)];
+        head.extend(cookie.map(|c| format!("Cookie: {}", c)));
+        head
+    }
+
+    fn set_cookie(res: &Response) -> Option<&str> {
+        res.headers.iter().find(|(n, _)| n == "Set-Cookie").map(|(_, v)| v.split(';').next().unwrap())
+    }
+
+    #[test]
+    fn cookies_of_two_servers_do_not_collide() {
+        let mut m = MemoryAssets::new();
+        m.insert("/index.html", &b"hi"[..]);
+        let ((a, port_a), (b, port_b)) = (("aaaa", 50001), ("bbbb", 50002));
+
+        let (res, _) = handle(&get(port_a, "/index.html?__webviewx_token=aaaa", None), &m, a, port_a);
+        let cookie_a = set_cookie(&res).unwrap().to_owned();
+        let (res, _) = handle(&get(port_b, "/index.html?__webviewx_token=bbbb", None), &m, b, port_b);
+        let cookie_b = set_cookie(&res).unwrap().to_owned();
+        assert_eq!((cookie_a.as_str(), cookie_b.as_str()), ("webviewx_token_50001=aaaa", "webviewx_token_50002=bbbb"));
+
+        // The browser sends both cookies to both ports.
+        let both = format!("{}; {}", cookie_b, cookie_a);
+        assert_eq!(handle(&get(port_a, "/index.html", Some(&both)), &m, a, port_a).0.status, 200);
+        assert_eq!(handle(&get(port_b, "/index.html", Some(&both)), &m, b, port_b).0.status, 200);
+        assert_eq!(handle(&get(port_a, "/index.html", Some(&cookie_b)), &m, a, port_a).0.status, 403);
+    }
+
+    #[test]
+    fn host_must_name_the_server() {
+        let mut m = MemoryAssets::new();
+        m.insert("/index.html", &b"hi"[..]);
+        let mut head = get(50001, "/index.html?__webviewx_token=aaaa", None);
+        assert_eq!(handle(&head, &m, "aaaa", 50001).0.status, 303);
+        head[1] = "Host: rebound.example.com:50001".to_owned();
+        assert_eq!(handle(&head, &m, "aaaa", 50001).0.status, 403);
+        head.remove(1);
+        assert_eq!(handle(&head, &m, "aaaa", 50001).0.status, 403);
+    }
+    #[test]
+    fn token_is_redirected_away() {
+        let mut m = MemoryAssets::new();
+        m.insert("/index.html", &b"hi"[..]);
+        let header = |res: &Response, name: &str| {
+            res.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+        };
+        let (res, _) = handle(&get(50001, "/index.html?a=1&__webviewx_token=aaaa&b=2", None), &m, "aaaa", 50001);
+        assert_eq!((res.status, header(&res, "Location").as_deref()), (303, Some("/index.html?a=1&b=2")));
+        assert!(set_cookie(&res).is_some());
+        let (res, _) = handle(&get(50001, "/index.html", Some("webviewx_token_50001=aaaa")), &m, "aaaa", 50001);
+        assert_eq!(res.status, 200);
+        assert_eq!(header(&res, "Referrer-Policy").as_deref(), Some("no-referrer"));
+        assert_eq!(without_token("/?__webviewx_token=aaaa"), "/");
+        assert_eq!(without_token("/a?x=1"), "/a?x=1");
+    }
+}
@@ -0,0 +1,100 @@
+//! Stylesheets injected by the application.
+//!
+//! A stylesheet is a `<style>` element added by a main-frame init script, so it comes back
+//! with every new document, and added to the current document right away. Removing it
+//! removes both.
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::bridge;
+use crate::script::ScriptId;
+
+static NEXT_ELEMENT: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a stylesheet added with
+/// [`WebView::inject_css()`](../enum.WebView.html#method.inject_css).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CssId {
+    pub(crate) element: u64,
+    pub(crate) script: ScriptId,
+}
+
+/// A new, process-wide unique id for a `<style>` element.
+pub(crate) fn next_element() -> u64 {
+    NEXT_ELEMENT.fetch_add(1, Ordering::Relaxed)
+}
+
+fn element_id(element: u64) -> String {
+    format!("__webviewx_css_{}", element)
+}
+
+/// Script that adds `css` to the document as `<style>` element `element`, waiting for the
+/// document element if the document has just been created.
+pub(crate) fn insert(element: u64, css: &str) -> String {
+    format!(r#"(function () {{
+  var id = "{id}", css = {css};
+  function insert() {{
+    var root = document.head || document.documentElement;
+    if (!root) return false;
+    if (document.getElementById(id)) return true;
+    var style = document.createElement('style');
+    style.id = id;
+    style.type = 'text/css';
+    root.appendChild(style);
+    // Old document modes only take the text through the stylesheet object once attached.
+    if (style.styleSheet) style.styleSheet.cssText = css;
+    else style.appendChild(document.createTextNode(css));
+    return true;
+  }}
+  if (!insert()) {{
+    if (document.addEventListener) document.addEventListener('DOMContentLoaded', insert);
+    else document.attachEvent('onreadystatechange', insert);
+  }}
+}})();"#, id = element_id(element), css = bridge::js_string(css))
+}
+
+/// Script that removes `<style>` element `element` from the document.
+pub(crate) fn remove(element: u64) -> String {
+    format!(
+        "(function () {{ var s = document.getElementById(\"{}\"); if (s) s.parentNode.removeChild(s); }})();",
+        element_id(element)
+    )
+}
+
+/// A `:root` rule setting the custom properties in `vars`. The `--` prefix of the names is
+/// optional; names and values are used as they are.
+pub fn root_variables<I, K, V>(vars: I) -> String
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut css = String::from(":root {\n");
+    for (name, value) in vars {
+        let name = name.as_ref();
+        let dashes = if name.starts_with("--") { "" } else { "--" };
+        css.push_str(&format!("  {}{}: {};\n", dashes, name, value.as_ref()));
+    }
+    css.push_str("}\n");
+    css
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn variables() {
+        let mut vars = BTreeMap::new();
+        vars.insert("bg", "white".to_owned());
+        vars.insert("--fg", "black".to_owned());
+        assert_eq!(root_variables(&vars), ":root {\n  --fg: black;\n  --bg: white;\n}\n");
+        assert_eq!(root_variables(Vec::<(&str, &str)>::new()), ":root {\n}\n");
+    }
+
+    #[test]
+    fn scripts() {
+        let js = insert(7, "</style><script>");
+        assert!(js.contains(r#"var id = "__webviewx_css_7", css = "\u003c/style>\u003cscript>";"#));
+        assert!(remove(7).contains(r#"getElementById("__webviewx_css_7")"#));
+    }
+}
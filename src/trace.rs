@@ -0,0 +1,52 @@
+//! Optional `tracing` instrumentation.
+//!
+//! With the `tracing` feature the macros forward to the `tracing` crate. Without it they
+//! expand to nothing, their arguments are not evaluated and [`now()`] measures nothing.
+//!
+//! Spans: `install_webview2`, `build`, `webview2::create` and `ipc` for every bridge
+//! message. Events: engine selection, environment and controller creation, navigations,
+//! process failures and recovery, and `eval` round trips.
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_span {
+    (info, $($args:tt)+) => { tracing::info_span!($($args)+).entered() };
+    (debug, $($args:tt)+) => { tracing::debug_span!($($args)+).entered() };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span {
+    ($($args:tt)+) => { $crate::trace::Off };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    (info, $($args:tt)+) => { tracing::info!($($args)+) };
+    (debug, $($args:tt)+) => { tracing::debug!($($args)+) };
+    (warn, $($args:tt)+) => { tracing::warn!($($args)+) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($($args:tt)+) => { () };
+}
+
+/// Stands in for spans and instants without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Off;
+
+#[cfg(feature = "tracing")]
+pub(crate) type Start = std::time::Instant;
+#[cfg(not(feature = "tracing"))]
+pub(crate) type Start = Off;
+
+/// Start of an interval reported by a later event.
+#[cfg(feature = "tracing")]
+pub(crate) fn now() -> Start {
+    std::time::Instant::now()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn now() -> Start {
+    Off
+}
@@ -0,0 +1,78 @@
+//! Polling file watcher used for live reload in development mode.
+//!
+//! Polls instead of using change notifications to stay dependency free; it only runs in
+//! development builds.
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Stops watching when dropped.
+pub(crate) struct Watcher {
+    stop: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    /// Calls `on_change` on the watcher thread whenever a file below `paths` is added, removed
+    /// or modified. Bursts of changes, e.g. a bundler writing its output, are reported once
+    /// the files settle.
+    pub(crate) fn start<F: Fn() + Send + 'static>(paths: Vec<PathBuf>, on_change: F) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let s = stop.clone();
+        let _ = thread::Builder::new().name("webviewx-watch".to_owned()).spawn(move || {
+            let mut reported = fingerprint(&paths);
+            let mut last = reported;
+            while !s.load(Ordering::SeqCst) {
+                thread::sleep(POLL_INTERVAL);
+                let current = fingerprint(&paths);
+                if current == last && current != reported {
+                    reported = current;
+                    on_change();
+                }
+                last = current;
+            }
+        });
+        Watcher { stop }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Hash of the names, sizes and modification times of all files below `paths`.
+fn fingerprint(paths: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        visit(path, &mut hasher);
+    }
+    hasher.finish()
+}
+
+fn visit(path: &Path, hasher: &mut DefaultHasher) {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return
+    };
+    path.hash(hasher);
+    if meta.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            let mut entries: Vec<_> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+            entries.sort();
+            for entry in entries {
+                visit(&entry, hasher);
+            }
+        }
+    } else {
+        meta.len().hash(hasher);
+        meta.modified().ok().hash(hasher);
+    }
+}
@@ -0,0 +1,289 @@
+//! Win32 window helpers shared by both engines.
+//!
+//! Everything here works on a plain `HWND`, so it applies equally to the window
+//! created by `web_view` and the one created in `wv2`.
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::path::PathBuf;
+use std::ptr;
+use winapi::{
+    shared::basetsd::*, shared::minwindef::*, shared::windef::*, um::commctrl::*,
+    um::libloaderapi::*, um::winbase::MulDiv, um::wingdi::*, um::winuser::*,
+};
+use raw_window_handle::{RawWindowHandle, Win32WindowHandle};
+use crate::{Bounds, Monitor, window_state};
+
+/// Per-window state kept alive by the subclass installed in [`ext`].
+///
+/// Most Win32 calls on the window re-enter [`subclass_proc`], so the state is only ever
+/// shared; values are copied out and no borrow is held across such a call.
+#[derive(Default)]
+struct WindowExt {
+    min_size: Cell<Option<(i32,i32)>>,
+    max_size: Cell<Option<(i32,i32)>>,
+    /// Style and placement to restore when leaving fullscreen.
+    restore: Cell<Option<(LONG_PTR, WINDOWPLACEMENT)>>,
+    /// Where to save the placement when the window is destroyed.
+    state_file: RefCell<Option<PathBuf>>,
+    /// Hide the caption and borders by making the whole window the client area.
+    frameless: Cell<bool>,
+}
+
+const SUBCLASS_ID: UINT_PTR = 0x5756_5800;
+
+unsafe extern "system" fn subclass_proc(
+    hwnd: HWND,
+    msg: UINT,
+    w_param: WPARAM,
+    l_param: LPARAM,
+    id: UINT_PTR,
+    data: DWORD_PTR,
+) -> LRESULT {
+    let ext = &*(data as *const WindowExt);
+    match msg {
+        WM_GETMINMAXINFO => {
+            let r = DefSubclassProc(hwnd, msg, w_param, l_param);
+            let info = &mut *(l_param as *mut MINMAXINFO);
+            let dpi = dpi_for_window(hwnd);
+            if let Some((w, h)) = ext.min_size.get() {
+                info.ptMinTrackSize.x = MulDiv(w, dpi, USER_DEFAULT_SCREEN_DPI);
+                info.ptMinTrackSize.y = MulDiv(h, dpi, USER_DEFAULT_SCREEN_DPI);
+            }
+            if let Some((w, h)) = ext.max_size.get() {
+                info.ptMaxTrackSize.x = MulDiv(w, dpi, USER_DEFAULT_SCREEN_DPI);
+                info.ptMaxTrackSize.y = MulDiv(h, dpi, USER_DEFAULT_SCREEN_DPI);
+            }
+            r
+        }
+        WM_NCCALCSIZE if ext.frameless.get() && w_param != 0 => {
+            // A maximized window extends past the monitor by the (invisible) frame size.
+            if IsZoomed(hwnd) != 0 {
+                let params = &mut *(l_param as *mut NCCALCSIZE_PARAMS);
+                let mut info: MONITORINFO = mem::zeroed();
+                info.cbSize = mem::size_of::<MONITORINFO>() as DWORD;
+                GetMonitorInfoW(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST), &mut info);
+                params.rgrc[0] = info.rcWork;
+            }
+            0
+        }
+        WM_DESTROY => {
+            let state_file = ext.state_file.borrow_mut().take();
+            if let Some(path) = state_file {
+                set_fullscreen(hwnd, false);
+                let _ = window_state::capture(hwnd).save(&path);
+            }
+            DefSubclassProc(hwnd, msg, w_param, l_param)
+        }
+        WM_NCDESTROY => {
+            RemoveWindowSubclass(hwnd, Some(subclass_proc), id);
+            drop(Box::from_raw(data as *mut WindowExt));
+            DefSubclassProc(hwnd, msg, w_param, l_param)
+        }
+        _ => DefSubclassProc(hwnd, msg, w_param, l_param),
+    }
+}
+
+/// Returns the state attached to `hwnd`, subclassing the window on first use.
+///
+/// The state is released on `WM_NCDESTROY`.
+fn ext<'a>(hwnd: HWND) -> &'a WindowExt {
+    unsafe {
+        let mut data: DWORD_PTR = 0;
+        if GetWindowSubclass(hwnd, Some(subclass_proc), SUBCLASS_ID, &mut data) == 0 {
+            data = Box::into_raw(Box::new(WindowExt::default())) as DWORD_PTR;
+            SetWindowSubclass(hwnd, Some(subclass_proc), SUBCLASS_ID, data);
+        }
+        &*(data as *const WindowExt)
+    }
+}
+
+/// DPI of the monitor `hwnd` is on. Falls back to the system DPI before Windows 10.
+pub(crate) fn dpi_for_window(hwnd: HWND) -> i32 {
+    unsafe {
+        let user32 = GetModuleHandleA(b"user32.dll\0".as_ptr() as *const i8);
+        let get_dpi_for_window = GetProcAddress(user32, b"GetDpiForWindow\0".as_ptr() as *const i8);
+        if !get_dpi_for_window.is_null() {
+            let get_dpi_for_window: extern "system" fn(HWND) -> UINT = mem::transmute(get_dpi_for_window);
+            get_dpi_for_window(hwnd) as i32
+        } else {
+            let hdc = GetDC(ptr::null_mut());
+            let dpi = GetDeviceCaps(hdc, LOGPIXELSX);
+            ReleaseDC(ptr::null_mut(), hdc);
+            dpi
+        }
+    }
+}
+
+fn scale(hwnd: HWND, v: i32) -> i32 {
+    unsafe { MulDiv(v, dpi_for_window(hwnd), USER_DEFAULT_SCREEN_DPI) }
+}
+
+pub(crate) fn raw_window_handle(hwnd: HWND) -> RawWindowHandle {
+    let mut handle = Win32WindowHandle::empty();
+    handle.hwnd = hwnd as _;
+    handle.hinstance = unsafe { GetWindowLongPtrW(hwnd, GWLP_HINSTANCE) } as _;
+    RawWindowHandle::Win32(handle)
+}
+
+pub(crate) fn set_window_bounds(hwnd: HWND, bounds: Bounds) {
+    let parent = unsafe {
+        if GetWindowLongPtrW(hwnd, GWL_STYLE) as DWORD & WS_CHILD != 0 { Some(GetParent(hwnd)) } else { None }
+    };
+    place_window(hwnd, parent, bounds, SWP_NOZORDER | SWP_NOACTIVATE);
+}
+
+/// Turns an existing top-level window into a child of `parent`.
+pub(crate) fn attach_to_parent(hwnd: HWND, parent: HWND, bounds: Bounds) {
+    unsafe {
+        SetWindowLongPtrW(hwnd, GWL_STYLE, (WS_CHILD | WS_VISIBLE | WS_CLIPCHILDREN) as _);
+        SetParent(hwnd, parent);
+    }
+    place_window(hwnd, Some(parent), bounds, SWP_NOZORDER | SWP_NOACTIVATE | SWP_FRAMECHANGED);
+}
+
+/// Bounds of a child window are relative to its parent, so they are scaled with the
+/// parent's DPI rather than the system DPI.
+fn place_window(hwnd: HWND, parent: Option<HWND>, bounds: Bounds, flags: UINT) {
+    let reference = parent.unwrap_or(hwnd);
+    unsafe {
+        SetWindowPos(
+            hwnd,
+            ptr::null_mut(),
+            scale(reference, bounds.x),
+            scale(reference, bounds.y),
+            scale(reference, bounds.width),
+            scale(reference, bounds.height),
+            flags,
+        );
+    }
+}
+
+pub(crate) fn set_position(hwnd: HWND, x: i32, y: i32) {
+    unsafe {
+        SetWindowPos(hwnd, ptr::null_mut(), scale(hwnd, x), scale(hwnd, y), 0, 0,
+                     SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE);
+    }
+}
+
+pub(crate) fn set_size(hwnd: HWND, width: i32, height: i32) {
+    unsafe {
+        SetWindowPos(hwnd, ptr::null_mut(), 0, 0, scale(hwnd, width), scale(hwnd, height),
+                     SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE);
+    }
+}
+
+pub(crate) fn set_min_size(hwnd: HWND, size: Option<(i32,i32)>) {
+    ext(hwnd).min_size.set(size);
+}
+
+pub(crate) fn set_max_size(hwnd: HWND, size: Option<(i32,i32)>) {
+    ext(hwnd).max_size.set(size);
+}
+
+pub(crate) fn set_state_file(hwnd: HWND, path: Option<PathBuf>) {
+    *ext(hwnd).state_file.borrow_mut() = path;
+}
+
+/// Removes the caption and borders. The window keeps its styles, so it can still be
+/// resized, snapped and minimized through the bridge commands.
+pub(crate) fn set_frameless(hwnd: HWND, frameless: bool) {
+    ext(hwnd).frameless.set(frameless);
+    unsafe {
+        SetWindowPos(hwnd, ptr::null_mut(), 0, 0, 0, 0,
+                     SWP_NOMOVE | SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE | SWP_FRAMECHANGED);
+    }
+}
+
+/// Work area of the requested monitor, in physical pixels.
+fn monitor_work_area(hwnd: HWND, monitor: Monitor) -> RECT {
+    unsafe {
+        let hmonitor = match monitor {
+            Monitor::Primary => MonitorFromPoint(POINT { x: 0, y: 0 }, MONITOR_DEFAULTTOPRIMARY),
+            Monitor::Current => MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST),
+        };
+        let mut info: MONITORINFO = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFO>() as DWORD;
+        GetMonitorInfoW(hmonitor, &mut info);
+        info.rcWork
+    }
+}
+
+pub(crate) fn center(hwnd: HWND, monitor: Monitor) {
+    let work = monitor_work_area(hwnd, monitor);
+    unsafe {
+        let mut r: RECT = mem::zeroed();
+        GetWindowRect(hwnd, &mut r);
+        let (w, h) = (r.right - r.left, r.bottom - r.top);
+        SetWindowPos(
+            hwnd,
+            ptr::null_mut(),
+            work.left + (work.right - work.left - w) / 2,
+            work.top + (work.bottom - work.top - h) / 2,
+            0,
+            0,
+            SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+        );
+    }
+}
+
+pub(crate) fn show(hwnd: HWND, maximized: bool, minimized: bool) {
+    let cmd = if maximized {
+        SW_SHOWMAXIMIZED
+    } else if minimized {
+        SW_SHOWMINIMIZED
+    } else {
+        SW_SHOW
+    };
+    unsafe {
+        ShowWindow(hwnd, cmd);
+        UpdateWindow(hwnd);
+    }
+}
+
+pub(crate) fn set_maximized(hwnd: HWND, maximized: bool) {
+    unsafe { ShowWindow(hwnd, if maximized { SW_MAXIMIZE } else { SW_RESTORE }); }
+}
+
+pub(crate) fn set_minimized(hwnd: HWND, minimized: bool) {
+    unsafe { ShowWindow(hwnd, if minimized { SW_MINIMIZE } else { SW_RESTORE }); }
+}
+
+pub(crate) fn set_always_on_top(hwnd: HWND, always_on_top: bool) {
+    let after = if always_on_top { HWND_TOPMOST } else { HWND_NOTOPMOST };
+    unsafe {
+        SetWindowPos(hwnd, after, 0, 0, 0, 0, SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE);
+    }
+}
+
+pub(crate) fn is_fullscreen(hwnd: HWND) -> bool {
+    ext(hwnd).restore.get().is_some()
+}
+
+/// Covers the whole monitor without borders, or restores the previous style and placement.
+pub(crate) fn set_fullscreen(hwnd: HWND, fullscreen: bool) {
+    let ext = ext(hwnd);
+    unsafe {
+        if fullscreen {
+            if ext.restore.get().is_some() {
+                return
+            }
+            let style = GetWindowLongPtrW(hwnd, GWL_STYLE);
+            let mut placement: WINDOWPLACEMENT = mem::zeroed();
+            placement.length = mem::size_of::<WINDOWPLACEMENT>() as UINT;
+            GetWindowPlacement(hwnd, &mut placement);
+            let mut info: MONITORINFO = mem::zeroed();
+            info.cbSize = mem::size_of::<MONITORINFO>() as DWORD;
+            GetMonitorInfoW(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST), &mut info);
+            SetWindowLongPtrW(hwnd, GWL_STYLE, style & !(WS_OVERLAPPEDWINDOW as LONG_PTR));
+            let m = info.rcMonitor;
+            SetWindowPos(hwnd, HWND_TOP, m.left, m.top, m.right - m.left, m.bottom - m.top,
+                         SWP_NOOWNERZORDER | SWP_FRAMECHANGED);
+            ext.restore.set(Some((style, placement)));
+        } else if let Some((style, placement)) = ext.restore.take() {
+            SetWindowLongPtrW(hwnd, GWL_STYLE, style);
+            SetWindowPlacement(hwnd, &placement);
+            SetWindowPos(hwnd, ptr::null_mut(), 0, 0, 0, 0,
+                         SWP_NOMOVE | SWP_NOSIZE | SWP_NOZORDER | SWP_NOOWNERZORDER | SWP_FRAMECHANGED);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Saving and restoring the window placement across runs.
+//!
+//! The state is a small `key=value` file stored in `%APPDATA%\<app>\window-state`.
+//! Coordinates are physical screen pixels of the restored (not maximized) window.
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use winapi::{shared::minwindef::*, shared::windef::*, um::winuser::*};
+use crate::Bounds;
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct WindowState {
+    pub bounds: Bounds,
+    pub maximized: bool,
+    /// Device name of the monitor the window was on, e.g. `\\.\DISPLAY1`.
+    pub monitor: String,
+}
+
+impl WindowState {
+    /// Default location of the state file for `app_name`.
+    pub fn path(app_name: &str) -> Option<PathBuf> {
+        std::env::var_os("APPDATA").map(|dir| Path::new(&dir).join(app_name).join("window-state"))
+    }
+
+    pub fn load(path: &Path) -> Option<WindowState> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    fn parse(s: &str) -> Option<WindowState> {
+        let mut state = WindowState {
+            bounds: Bounds { x: 0, y: 0, width: 0, height: 0 },
+            maximized: false,
+            monitor: String::new(),
+        };
+        for line in s.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key.trim() {
+                "x" => state.bounds.x = value.trim().parse().ok()?,
+                "y" => state.bounds.y = value.trim().parse().ok()?,
+                "width" => state.bounds.width = value.trim().parse().ok()?,
+                "height" => state.bounds.height = value.trim().parse().ok()?,
+                "maximized" => state.maximized = value.trim().parse().ok()?,
+                "monitor" => state.monitor = value.trim().to_owned(),
+                _ => {}
+            }
+        }
+        if state.bounds.width <= 0 || state.bounds.height <= 0 {
+            return None
+        }
+        Some(state)
+    }
+}
+
+impl std::fmt::Display for WindowState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "x={}", self.bounds.x)?;
+        writeln!(f, "y={}", self.bounds.y)?;
+        writeln!(f, "width={}", self.bounds.width)?;
+        writeln!(f, "height={}", self.bounds.height)?;
+        writeln!(f, "maximized={}", self.maximized)?;
+        writeln!(f, "monitor={}", self.monitor)
+    }
+}
+
+/// Fits `bounds` into one of the monitor work areas.
+///
+/// A window that lies completely inside a work area is returned unchanged. Otherwise the
+/// window is moved onto the work area it overlaps most, or `preferred` (then the first one)
+/// when it overlaps none, and shrunk if it is larger than that area.
+pub(crate) fn clamp(bounds: Bounds, work_areas: &[Bounds], preferred: Option<usize>) -> Bounds {
+    fn overlap(a: &Bounds, b: &Bounds) -> i64 {
+        let w = (a.x + a.width).min(b.x + b.width) - a.x.max(b.x);
+        let h = (a.y + a.height).min(b.y + b.height) - a.y.max(b.y);
+        if w <= 0 || h <= 0 { 0 } else { w as i64 * h as i64 }
+    }
+
+    let area = work_areas
+        .iter()
+        .filter(|a| overlap(&bounds, a) > 0)
+        .max_by_key(|a| overlap(&bounds, a))
+        .or_else(|| preferred.and_then(|i| work_areas.get(i)))
+        .or_else(|| work_areas.first());
+    let area = match area {
+        Some(a) => a,
+        None => return bounds
+    };
+
+    let width = bounds.width.min(area.width);
+    let height = bounds.height.min(area.height);
+    Bounds {
+        x: bounds.x.max(area.x).min(area.x + area.width - width),
+        y: bounds.y.max(area.y).min(area.y + area.height - height),
+        width,
+        height,
+    }
+}
+
+/// Monitors as `(device name, work area)`, primary monitor first.
+fn monitors() -> Vec<(String, Bounds)> {
+    unsafe extern "system" fn callback(hmonitor: HMONITOR, _: HDC, _: LPRECT, data: LPARAM) -> BOOL {
+        let monitors = &mut *(data as *mut Vec<(bool, String, Bounds)>);
+        let mut info: MONITORINFOEXW = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFOEXW>() as DWORD;
+        if GetMonitorInfoW(hmonitor, &mut info as *mut _ as LPMONITORINFO) != 0 {
+            let r = info.rcWork;
+            monitors.push((
+                info.dwFlags & MONITORINFOF_PRIMARY != 0,
+                device_name(&info.szDevice),
+                Bounds { x: r.left, y: r.top, width: r.right - r.left, height: r.bottom - r.top },
+            ));
+        }
+        TRUE
+    }
+
+    let mut monitors: Vec<(bool, String, Bounds)> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(ptr::null_mut(), ptr::null(), Some(callback), &mut monitors as *mut _ as LPARAM);
+    }
+    monitors.sort_by_key(|m| !m.0);
+    monitors.into_iter().map(|(_, name, area)| (name, area)).collect()
+}
+
+fn device_name(name: &[u16]) -> String {
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    String::from_utf16_lossy(&name[..len])
+}
+
+/// Offset between workspace coordinates used by `WINDOWPLACEMENT` and screen coordinates.
+fn workspace_offset() -> (i32, i32) {
+    unsafe {
+        let mut info: MONITORINFO = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFO>() as DWORD;
+        GetMonitorInfoW(MonitorFromPoint(POINT { x: 0, y: 0 }, MONITOR_DEFAULTTOPRIMARY), &mut info);
+        (info.rcWork.left - info.rcMonitor.left, info.rcWork.top - info.rcMonitor.top)
+    }
+}
+
+/// Reads the current placement of `hwnd`.
+pub(crate) fn capture(hwnd: HWND) -> WindowState {
+    unsafe {
+        let mut placement: WINDOWPLACEMENT = mem::zeroed();
+        placement.length = mem::size_of::<WINDOWPLACEMENT>() as UINT;
+        GetWindowPlacement(hwnd, &mut placement);
+        let (dx, dy) = workspace_offset();
+        let r = placement.rcNormalPosition;
+
+        let mut info: MONITORINFOEXW = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFOEXW>() as DWORD;
+        GetMonitorInfoW(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST), &mut info as *mut _ as LPMONITORINFO);
+
+        WindowState {
+            bounds: Bounds { x: r.left + dx, y: r.top + dy, width: r.right - r.left, height: r.bottom - r.top },
+            maximized: placement.showCmd == SW_SHOWMAXIMIZED as UINT,
+            monitor: device_name(&info.szDevice),
+        }
+    }
+}
+
+/// Moves `hwnd` to the saved placement, clamped to the current monitor layout.
+///
+/// Returns whether the window should be shown maximized.
+pub(crate) fn restore(hwnd: HWND, state: &WindowState) -> bool {
+    let monitors = monitors();
+    let preferred = monitors.iter().position(|(name, _)| *name == state.monitor);
+    let areas: Vec<Bounds> = monitors.into_iter().map(|(_, area)| area).collect();
+    let b = clamp(state.bounds, &areas, preferred);
+    unsafe {
+        SetWindowPos(hwnd, ptr::null_mut(), b.x, b.y, b.width, b.height, SWP_NOZORDER | SWP_NOACTIVATE);
+    }
+    state.maximized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn b(x: i32, y: i32, width: i32, height: i32) -> Bounds {
+        Bounds { x, y, width, height }
+    }
+
+    #[test]
+    fn inside_is_unchanged() {
+        let areas = [b(0, 0, 1920, 1040), b(1920, 0, 1280, 984)];
+        assert_eq!(clamp(b(100, 100, 800, 600), &areas, None), b(100, 100, 800, 600));
+        assert_eq!(clamp(b(2000, 50, 800, 600), &areas, Some(0)), b(2000, 50, 800, 600));
+    }
+
+    #[test]
+    fn partially_offscreen_is_pulled_in() {
+        let areas = [b(0, 0, 1920, 1040)];
+        assert_eq!(clamp(b(1500, -50, 800, 600), &areas, None), b(1120, 0, 800, 600));
+        assert_eq!(clamp(b(-300, 900, 800, 600), &areas, None), b(0, 440, 800, 600));
+    }
+
+    #[test]
+    fn removed_monitor_falls_back_to_preferred_or_primary() {
+        let areas = [b(0, 0, 1920, 1040), b(-1280, 0, 1280, 984)];
+        assert_eq!(clamp(b(2500, 100, 800, 600), &areas, Some(1)), b(-800, 100, 800, 600));
+        assert_eq!(clamp(b(2500, 100, 800, 600), &areas, None), b(1120, 100, 800, 600));
+    }
+
+    #[test]
+    fn larger_than_work_area_is_shrunk() {
+        let areas = [b(0, 0, 1366, 728)];
+        assert_eq!(clamp(b(-10, -10, 1920, 1080), &areas, None), b(0, 0, 1366, 728));
+    }
+
+    #[test]
+    fn picks_area_with_most_overlap() {
+        let areas = [b(0, 0, 1920, 1040), b(1920, 0, 1920, 1040)];
+        assert_eq!(clamp(b(1800, 100, 800, 600), &areas, Some(0)), b(1920, 100, 800, 600));
+    }
+
+    #[test]
+    fn no_monitors_keeps_bounds() {
+        assert_eq!(clamp(b(5, 5, 10, 10), &[], None), b(5, 5, 10, 10));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let state = WindowState { bounds: b(-1200, 40, 1024, 768), maximized: true, monitor: r"\\.\DISPLAY2".to_owned() };
+        assert_eq!(WindowState::parse(&state.to_string()), Some(state));
+        assert_eq!(WindowState::parse("x=1\nwidth=0\nheight=10\n"), None);
+        assert_eq!(WindowState::parse("garbage"), None);
+    }
+}
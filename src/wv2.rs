@@ -1,24 +1,38 @@
 use webview2;
-use once_cell::unsync::OnceCell;
 use std::mem;
 use std::ptr;
 use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use webview2::Controller;
 use winapi::{
-    shared::minwindef::*, shared::windef::*, um::libloaderapi::*, um::winbase::MulDiv,
+    shared::minwindef::*, shared::windef::*, shared::winerror::ERROR_CLASS_ALREADY_EXISTS,
+    um::libloaderapi::*, um::winbase::MulDiv,
     um::wingdi::*, um::winuser::*,
 };
-use crate::{WVResult, WVError};
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::Instant;
+use crate::{WVResult, WVError, Bounds, SelectionReport, assets, bridge, window};
+use crate::assets::AssetResolver;
+use crate::console::{Console, ConsoleHandler};
+use crate::environment::{BrowserEnvironment, Environment};
+use crate::error_page::ErrorPageHandler;
+use crate::watch::Watcher;
+use crate::navigation::{Navigation, NavigationError, NavigationHandlers};
+use crate::policy::{NavigationPolicy, PolicyRequest};
+use crate::recovery::{ProcessFailedHandler, ProcessFailure, Recovery, RecoveryPolicy, Step};
+use crate::script::{self, EvalCallback, EvalError, InitScripts, ScriptId};
+use raw_window_handle::{HasRawWindowHandle, HasRawDisplayHandle, RawWindowHandle, RawDisplayHandle, WindowsDisplayHandle};
+
+/// Posted by the live reload watcher.
+const WM_RELOAD: UINT = WM_APP + 0x57;
+/// Timer of a scheduled recovery step.
+const RECOVERY_TIMER: usize = 0x57;
 
 fn utf_16_null_terminiated(x: &str) -> Vec<u16> {
     x.encode_utf16().chain(std::iter::once(0)).collect()
 }
-fn message_box(hwnd: HWND, text: &str, caption: &str, _type: u32) -> i32 {
-    let text = utf_16_null_terminiated(text);
-    let caption = utf_16_null_terminiated(caption);
-
-    unsafe { MessageBoxW(hwnd, text.as_ptr(), caption.as_ptr(), _type) }
-}
 
 pub struct WebView2Builder {
     pub background_color : (u8,u8,u8,u8),
@@ -30,6 +44,22 @@ pub struct WebView2Builder {
     pub resizable: bool,
     pub invoke_handler: Option<fn (&mut WebView2, data:&str)>,
     pub frameless: bool,
+    pub parent: Option<HWND>,
+    pub bounds: Option<Bounds>,
+    pub visible: bool,
+    pub scripts: Vec<String>,
+    pub init_scripts: Vec<(String, bool)>,
+    pub assets: Option<(String, Arc<dyn AssetResolver>)>,
+    pub navigation: NavigationHandlers,
+    pub policy: Option<NavigationPolicy>,
+    pub window_commands: Option<NavigationPolicy>,
+    pub on_process_failed: Option<ProcessFailedHandler>,
+    pub recovery: RecoveryPolicy,
+    pub error_page: Option<ErrorPageHandler>,
+    pub on_console_message: Option<ConsoleHandler>,
+    pub remote_debugging_port: Option<u16>,
+    pub environment: BrowserEnvironment,
+    pub shared_environment: Option<Environment>,
 }
 
 impl Default for WebView2Builder {
@@ -61,76 +91,84 @@ window.chrome.webview.addEventListener('message', event => alert('Received messa
             resizable: true,
             invoke_handler: None,
             frameless: false,
+            parent: None,
+            bounds: None,
+            visible: true,
+            scripts: Vec::new(),
+            init_scripts: Vec::new(),
+            assets: None,
+            navigation: NavigationHandlers::default(),
+            policy: None,
+            window_commands: None,
+            on_process_failed: None,
+            recovery: RecoveryPolicy::default(),
+            error_page: None,
+            on_console_message: None,
+            remote_debugging_port: None,
+            environment: BrowserEnvironment::default(),
+            shared_environment: None,
         }
     }
 }
 
 mod wnd_proc_helper {
     use super::*;
-    use std::cell::UnsafeCell;
 
-    struct UnsafeSyncCell<T> {
-        inner: UnsafeCell<T>,
+    /// The window procedure closure of a window, kept in its `GWLP_USERDATA`.
+    struct Window {
+        f: Box<dyn Fn(HWND, UINT, WPARAM, LPARAM) -> isize>,
+        top_level: bool,
+        quit_on_destroy: Cell<bool>,
     }
 
-    impl<T> UnsafeSyncCell<T> {
-        const fn new(t: T) -> UnsafeSyncCell<T> {
-            UnsafeSyncCell {
-                inner: UnsafeCell::new(t),
-            }
-        }
+    thread_local! {
+        /// Top-level windows of the thread that are still open.
+        static TOP_LEVEL: Cell<usize> = const { Cell::new(0) };
     }
 
-    impl<T: Copy> UnsafeSyncCell<T> {
-        unsafe fn get(&self) -> T {
-            self.inner.get().read()
-        }
-
-        unsafe fn set(&self, v: T) {
-            self.inner.get().write(v)
+    /// Destroys `hwnd` without posting a quit message.
+    pub unsafe fn discard(hwnd: HWND) {
+        if let Some(window) = (GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const Window).as_ref() {
+            window.quit_on_destroy.set(false);
         }
+        DestroyWindow(hwnd);
     }
 
-    unsafe impl<T: Copy> Sync for UnsafeSyncCell<T> {}
-
-    static GLOBAL_F: UnsafeSyncCell<usize> = UnsafeSyncCell::new(0);
-
-    /// Use a closure as window procedure.
+    /// Use a closure as window procedure of `hwnd`, a window of a class whose procedure
+    /// is [`wnd_proc`].
     ///
-    /// The closure will be boxed and stored in a global variable. It will be
-    /// released upon WM_DESTROY. (It doesn't get to handle WM_DESTROY.)
-    pub unsafe fn as_global_wnd_proc<F: Fn(HWND, UINT, WPARAM, LPARAM) -> isize + 'static>(
-        f: F,
-    ) -> unsafe extern "system" fn(hwnd: HWND, msg: UINT, w_param: WPARAM, l_param: LPARAM) -> isize
-    {
-        let f_ptr = Box::into_raw(Box::new(f));
-        GLOBAL_F.set(f_ptr as usize);
-
-        unsafe extern "system" fn wnd_proc<F: Fn(HWND, UINT, WPARAM, LPARAM) -> isize + 'static>(
-            hwnd: HWND,
-            msg: UINT,
-            w_param: WPARAM,
-            l_param: LPARAM,
-        ) -> isize {
-            let f_ptr = GLOBAL_F.get() as *mut F;
-
-            if msg == WM_DESTROY {
-                Box::from_raw(f_ptr);
-                GLOBAL_F.set(0);
-                PostQuitMessage(0);
-                return 0;
-            }
-
-            if !f_ptr.is_null() {
-                let f = &*f_ptr;
+    /// The closure is boxed and released upon WM_DESTROY, which it gets to handle first.
+    /// Once the last top-level window of the thread is destroyed, a quit message is posted.
+    /// `top_level` is false for child windows living in the host's message loop.
+    pub unsafe fn attach<F: Fn(HWND, UINT, WPARAM, LPARAM) -> isize + 'static>(hwnd: HWND, f: F, top_level: bool) {
+        let window = Box::new(Window { f: Box::new(f), top_level, quit_on_destroy: Cell::new(true) });
+        if top_level {
+            TOP_LEVEL.with(|n| n.set(n.get() + 1));
+        }
+        SetWindowLongPtrW(hwnd, GWLP_USERDATA, Box::into_raw(window) as isize);
+    }
 
-                f(hwnd, msg, w_param, l_param)
-            } else {
-                DefWindowProcW(hwnd, msg, w_param, l_param)
+    pub unsafe extern "system" fn wnd_proc(hwnd: HWND, msg: UINT, w_param: WPARAM, l_param: LPARAM) -> isize {
+        let window = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Window;
+        if window.is_null() {
+            return DefWindowProcW(hwnd, msg, w_param, l_param)
+        }
+        if msg == WM_DESTROY {
+            ((*window).f)(hwnd, msg, w_param, l_param);
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+            let window = Box::from_raw(window);
+            if window.top_level {
+                let open = TOP_LEVEL.with(|n| {
+                    n.set(n.get() - 1);
+                    n.get()
+                });
+                if open == 0 && window.quit_on_destroy.get() {
+                    PostQuitMessage(0);
+                }
             }
+            return 0
         }
-
-        wnd_proc::<F>
+        ((*window).f)(hwnd, msg, w_param, l_param)
     }
 }
 
@@ -180,8 +218,167 @@ impl WebView2Builder {
         self
     }
 
-    /// Validates provided arguments and returns a new WebView if successful.
-    pub fn build(self) -> WVResult<WebView2> {
+    /// Adds a script that runs at the start of every document, before the page's own scripts.
+    pub fn script(mut self, js: &str) -> Self {
+        self.scripts.push(js.to_owned());
+        self
+    }
+
+    /// Adds an application script that runs at the start of every document, after the ones
+    /// added with [`script()`](#method.script). It can be removed later through the id
+    /// [`WebView2::init_scripts()`] lists for it.
+    pub fn init_script(mut self, js: &str, main_frame_only: bool) -> Self {
+        self.init_scripts.push((js.to_owned(), main_frame_only));
+        self
+    }
+
+    /// Sets the initial window visibility. A hidden window can be shown later with `ShowWindow`.
+    ///
+    /// Defaults to `true`.
+    pub fn visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    /// Serves `resolver` below `https://<scheme>.localhost/`. A `<scheme>://` URL passed to
+    /// [`url()`](#method.url) is rewritten to that origin.
+    pub fn assets(mut self, scheme: &str, resolver: Arc<dyn AssetResolver>) -> Self {
+        self.assets = Some((scheme.to_owned(), resolver));
+        self
+    }
+
+    /// Sets the navigation event callbacks.
+    pub fn navigation(mut self, handlers: NavigationHandlers) -> Self {
+        self.navigation = handlers;
+        self
+    }
+
+    /// Restricts navigations and new windows of the main frame.
+    pub fn policy(mut self, policy: NavigationPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Lets the pages matching `trusted` move, resize and close a frameless window.
+    pub fn window_commands(mut self, trusted: NavigationPolicy) -> Self {
+        self.window_commands = Some(trusted);
+        self
+    }
+
+    /// Called when a browser process fails, before the recovery policy runs.
+    pub fn on_process_failed(mut self, f: ProcessFailedHandler) -> Self {
+        self.on_process_failed = Some(f);
+        self
+    }
+
+    /// Sets how to bring the page back after a browser process failed.
+    pub fn recovery(mut self, policy: RecoveryPolicy) -> Self {
+        self.recovery = policy;
+        self
+    }
+
+    /// Shows the HTML `f` returns instead of the engine's page when a navigation fails.
+    pub fn error_page(mut self, f: ErrorPageHandler) -> Self {
+        self.error_page = Some(f);
+        self
+    }
+
+    /// Called with the records of the console capture script, which has to be added with
+    /// [`script()`](#method.script). They are logged either way.
+    pub fn on_console_message(mut self, f: ConsoleHandler) -> Self {
+        self.on_console_message = Some(f);
+        self
+    }
+
+    /// Allows DevTools and the default context menus.
+    ///
+    /// Defaults to `false`.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Passes `--remote-debugging-port` to the browser process.
+    pub fn remote_debugging_port(mut self, port: u16) -> Self {
+        self.remote_debugging_port = Some(port);
+        self
+    }
+
+    pub fn environment(mut self, environment: BrowserEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Creates the webview in `environment`, instead of a new one from
+    /// [`environment()`](#method.environment) and the remote debugging port.
+    pub fn shared_environment(mut self, environment: Environment) -> Self {
+        self.shared_environment = Some(environment);
+        self
+    }
+
+    /// Creates the WebView as a child window of `parent`, placed at `bounds`
+    /// relative to the parent's client area.
+    pub fn parent(mut self, parent: HWND, bounds: Bounds) -> Self {
+        self.parent = Some(parent);
+        self.bounds = Some(bounds);
+        self
+    }
+
+    /// Creates the window and waits for the webview in it, running the thread's message loop
+    /// meanwhile. On failure the window is gone again and the handlers are handed back, so the
+    /// caller can try another engine.
+    pub(crate) fn create(mut self) -> Result<WebView2, Box<CreateError>> {
+        let _span = trace_span!(info, "webview2::create");
+        let mut user_scripts = UserScripts::default();
+        for (js, main_frame_only) in &self.init_scripts {
+            user_scripts.scripts.add(js, *main_frame_only);
+        }
+        let assets = self.assets.take().map(|(scheme, resolver)| {
+            let origin = assets::origin(&scheme);
+            (scheme, origin, resolver)
+        });
+        let url = match &assets {
+            Some((scheme, origin, _)) => assets::rewrite_url(&self.url, scheme, origin),
+            None => self.url.clone()
+        };
+        let environment = self.shared_environment.take().unwrap_or_else(|| {
+            let mut settings = mem::take(&mut self.environment);
+            if let Some(port) = self.remote_debugging_port {
+                settings.arguments.push(format!("--remote-debugging-port={}", port));
+            }
+            Environment::new(settings)
+        });
+        let host = Rc::new(Host {
+            hwnd: Cell::new(ptr::null_mut()),
+            environment,
+            env: RefCell::new(None),
+            generation: Cell::new(0),
+            controller: RefCell::new(None),
+            scripts: mem::take(&mut self.scripts),
+            user_scripts: Rc::new(RefCell::new(user_scripts)),
+            navigation: Rc::new(RefCell::new(Navigation::new(mem::take(&mut self.navigation)))),
+            policy: Rc::new(RefCell::new(self.policy.take())),
+            window_commands: Rc::new(self.window_commands.take()),
+            assets,
+            current: Rc::new(RefCell::new(if crate::is_url(&url) { Load::Url(url) } else { Load::Html(url) })),
+            recovery: RefCell::new(Recovery::new(mem::take(&mut self.recovery))),
+            on_process_failed: RefCell::new(self.on_process_failed.take()),
+            error_page: RefCell::new(self.error_page.take()),
+            failed_url: Rc::new(RefCell::new(None)),
+            internal: Rc::new(Cell::new(false)),
+            console: Rc::new(RefCell::new(Console::new(self.on_console_message.take()))),
+            debug: self.debug,
+            step: Cell::new(None),
+            outcome: RefCell::new(None),
+            closed: Cell::new(false),
+        });
+        let fail = |reason: String| {
+            let navigation = mem::replace(&mut *host.navigation.borrow_mut(), Navigation::new(NavigationHandlers::default()));
+            let policy = host.policy.borrow_mut().take();
+            let error_page = host.error_page.borrow_mut().take();
+            let on_console_message = mem::replace(&mut *host.console.borrow_mut(), Console::new(None)).into_handler();
+            Box::new(CreateError { reason, closed: false, navigation: navigation.into_handlers(), policy, error_page, on_console_message })
+        };
         //set dpi aware
         unsafe {
             // Windows 10.
@@ -202,41 +399,52 @@ impl WebView2Builder {
             }
         }
 
-        let controller = Rc::new(OnceCell::<Controller>::new());
-        let controller_clone = controller.clone();
-        let controller_holder = controller.clone();
+        let wnd_host = host.clone();
 
         // Window procedure.
         let wnd_proc = move |hwnd, msg, w_param, l_param| match msg {
             WM_SIZE => {
-                if let Some(c) = controller.get() {
+                if let Some(c) = &*wnd_host.controller.borrow() {
                     let mut r = unsafe { mem::zeroed() };
                     unsafe {
                         GetClientRect(hwnd, &mut r);
                     }
-                    c.put_bounds(r).unwrap();
+                    let _ = c.put_bounds(r);
                 }
                 0
             }
             WM_MOVE => {
-                if let Some(c) = controller.get() {
+                if let Some(c) = &*wnd_host.controller.borrow() {
                     let _ = c.notify_parent_window_position_changed();
                 }
                 0
             }
             // Optimization: don't render the webview when the window is minimized.
             WM_SYSCOMMAND if w_param == SC_MINIMIZE => {
-                if let Some(c) = controller.get() {
-                    c.put_is_visible(false).unwrap();
+                if let Some(c) = &*wnd_host.controller.borrow() {
+                    let _ = c.put_is_visible(false);
                 }
                 unsafe { DefWindowProcW(hwnd, msg, w_param, l_param) }
             }
             WM_SYSCOMMAND if w_param == SC_RESTORE => {
-                if let Some(c) = controller.get() {
-                    c.put_is_visible(true).unwrap();
+                if let Some(c) = &*wnd_host.controller.borrow() {
+                    let _ = c.put_is_visible(true);
                 }
                 unsafe { DefWindowProcW(hwnd, msg, w_param, l_param) }
             }
+            WM_RELOAD => {
+                if let Some(w) = wnd_host.webview() {
+                    let _ = w.reload();
+                }
+                0
+            }
+            WM_TIMER if w_param == RECOVERY_TIMER => {
+                unsafe { KillTimer(hwnd, RECOVERY_TIMER) };
+                if let Some(step) = wnd_host.step.take() {
+                    wnd_host.recover(step);
+                }
+                0
+            }
             // High DPI support.
             WM_DPICHANGED => unsafe {
                 let rect = *(l_param as *const RECT);
@@ -251,6 +459,17 @@ impl WebView2Builder {
                 );
                 0
             },
+            // Lets the browser process end with the last webview.
+            WM_DESTROY => {
+                if wnd_host.outcome.borrow().is_none() {
+                    wnd_host.closed.set(true);
+                    *wnd_host.outcome.borrow_mut() = Some(Err("the window was closed".to_owned()));
+                }
+                if let Some(c) = wnd_host.controller.borrow_mut().take() {
+                    let _ = c.close();
+                }
+                0
+            }
             _ => unsafe { DefWindowProcW(hwnd, msg, w_param, l_param) },
         };
 
@@ -260,119 +479,526 @@ impl WebView2Builder {
         let class = WNDCLASSW {
             style: CS_HREDRAW | CS_VREDRAW,
             hCursor: unsafe { LoadCursorW(ptr::null_mut(), IDC_ARROW) },
-            lpfnWndProc: Some(unsafe { wnd_proc_helper::as_global_wnd_proc(wnd_proc) }),
+            lpfnWndProc: Some(wnd_proc_helper::wnd_proc),
             lpszClassName: class_name.as_ptr(),
             hInstance: h_instance,
             hbrBackground: (COLOR_WINDOW + 1) as HBRUSH,
             ..unsafe { mem::zeroed() }
         };
-        unsafe {
-            if RegisterClassW(&class) == 0 {
-                message_box(
-                    ptr::null_mut(),
-                    &format!("RegisterClassW failed: {}", std::io::Error::last_os_error()),
-                    "Error",
-                    MB_ICONERROR | MB_OK,
-                );
-                return Err(WVError::Cause("RegisterClassW failed"))
+        // Registered by the first webview of the process.
+        if unsafe { RegisterClassW(&class) } == 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_CLASS_ALREADY_EXISTS as i32) {
+                return Err(fail(format!("RegisterClassW failed: {}", e)))
             }
         }
 
         // Create window. (Standard windows GUI boilerplate).
         let window_title = utf_16_null_terminiated("WebView2 - Win 32");
-        let hdc = unsafe { GetDC(ptr::null_mut()) };
-        let dpi = unsafe { GetDeviceCaps(hdc, LOGPIXELSX) };
-        unsafe { ReleaseDC(ptr::null_mut(), hdc) };
-        let hwnd = unsafe {
-            CreateWindowExW(
-                0,
-                class_name.as_ptr(),
-                window_title.as_ptr(),
+        // Child bounds are relative to the parent, so they follow its DPI.
+        let dpi = match self.parent {
+            Some(parent) => window::dpi_for_window(parent),
+            None => unsafe {
+                let hdc = GetDC(ptr::null_mut());
+                let dpi = GetDeviceCaps(hdc, LOGPIXELSX);
+                ReleaseDC(ptr::null_mut(), hdc);
+                dpi
+            },
+        };
+        let (style, x, y, width, height, parent) = unsafe { match (self.parent, self.bounds) {
+            (Some(parent), Some(b)) => (
+                WS_CHILD | WS_VISIBLE | WS_CLIPCHILDREN,
+                MulDiv(b.x, dpi, USER_DEFAULT_SCREEN_DPI),
+                MulDiv(b.y, dpi, USER_DEFAULT_SCREEN_DPI),
+                MulDiv(b.width, dpi, USER_DEFAULT_SCREEN_DPI),
+                MulDiv(b.height, dpi, USER_DEFAULT_SCREEN_DPI),
+                parent,
+            ),
+            _ => (
                 WS_OVERLAPPEDWINDOW,
                 CW_USEDEFAULT,
                 CW_USEDEFAULT,
                 MulDiv(self.width, dpi, USER_DEFAULT_SCREEN_DPI),
                 MulDiv(self.height, dpi, USER_DEFAULT_SCREEN_DPI),
                 ptr::null_mut(),
+            ),
+        } };
+        let hwnd = unsafe {
+            CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                window_title.as_ptr(),
+                style,
+                x,
+                y,
+                width,
+                height,
+                parent,
                 ptr::null_mut(),
                 h_instance,
                 ptr::null_mut(),
             )
         };
         if hwnd.is_null() {
-            message_box(
-                ptr::null_mut(),
-                &format!(
-                    "CreateWindowExW failed: {}",
-                    std::io::Error::last_os_error()
-                ),
-                "Error",
-                MB_ICONERROR | MB_OK,
-            );
-            return Err(WVError::Cause("CreateWindowExW failed"))
+            return Err(fail(format!("CreateWindowExW failed: {}", std::io::Error::last_os_error())))
         }
-        unsafe {
-            ShowWindow(hwnd, SW_SHOW);
-            UpdateWindow(hwnd);
+        unsafe { wnd_proc_helper::attach(hwnd, wnd_proc, self.parent.is_none()) };
+        if self.frameless {
+            window::set_frameless(hwnd, true);
+        }
+        if self.visible {
+            unsafe {
+                ShowWindow(hwnd, SW_SHOW);
+                UpdateWindow(hwnd);
+            }
         }
 
+        host.hwnd.set(hwnd);
+        trace_event!(debug, "window created");
+
         // Create the webview.
-        let r = webview2::Environment::builder().build(move |env| {
-            env.unwrap().create_controller(hwnd, move |c| {
-                let c = c.unwrap();
-                // if let Ok(c2) = c.get_controller2() {
-                //     let c = self.background_color;
-                //     c2.put_default_background_color(webview2_sys::Color {
-                //         r: c.0,
-                //         g: c.1,
-                //         b: c.2,
-                //         a: c.3,
-                //     }).unwrap();
-                // } else {
-                //     eprintln!("failed to get interface to controller2");
-                // }
-
-                let mut r = unsafe { mem::zeroed() };
-                unsafe {
-                    GetClientRect(hwnd, &mut r);
+        host.create_controller();
+        // Both steps complete asynchronously, through the message loop.
+        let mut msg: MSG = unsafe { mem::zeroed() };
+        while host.outcome.borrow().is_none() {
+            match unsafe { GetMessageW(&mut msg, ptr::null_mut(), 0, 0) } {
+                -1 => *host.outcome.borrow_mut() = Some(Err("the message loop failed".to_owned())),
+                0 => {
+                    // Leave the quit message to the application's loop.
+                    unsafe { PostQuitMessage(msg.wParam as i32) };
+                    host.closed.set(true);
+                    *host.outcome.borrow_mut() = Some(Err("the application quit".to_owned()));
                 }
-
-                c.put_bounds(r).unwrap();
-
-                let w = c.get_webview().unwrap();
-                // Communication.
-                w.navigate_to_string(self.url.as_str() ).unwrap();
-                // Receive message from webpage.
-                w.add_web_message_received(|w, msg| {
-                    let msg = msg.try_get_web_message_as_string()?;
-                    // Send it back.
-                    w.post_web_message_as_string(&msg)
-                }).unwrap();
-                controller_clone.set(c).unwrap();
-                Ok(())
-            })
-        });
-        if let Err(e) = r {
-            message_box(
-                ptr::null_mut(),
-                &format!("Creating WebView2 Environment failed: {}\n", e),
-                "Error",
-                MB_ICONERROR | MB_OK,
-            );
-            return Err(WVError::Cause("Creating WebView2 Environment failed"));
+                _ => unsafe {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+        }
+        let outcome = host.outcome.borrow().clone();
+        if let Some(Err(reason)) = outcome {
+            if unsafe { IsWindow(hwnd) } != 0 {
+                unsafe { wnd_proc_helper::discard(hwnd) };
+            }
+            let mut e = fail(reason);
+            e.closed = host.closed.get();
+            return Err(e)
         }
 
         Ok( WebView2 {
             hwnd : hwnd,
-            wv: controller_holder
+            scheme: host.assets.as_ref().map(|(scheme, _, _)| scheme.clone()),
+            host,
+            watcher: None,
+            report: SelectionReport::default(),
         } )
 
     }
 }
 
+/// Why [`WebView2Builder::create()`] failed.
+pub(crate) struct CreateError {
+    pub reason: String,
+    /// The window was closed or the application quit before WebView2 was ready, so the
+    /// engine did not fail.
+    pub closed: bool,
+    pub navigation: NavigationHandlers,
+    pub policy: Option<NavigationPolicy>,
+    pub error_page: Option<ErrorPageHandler>,
+    pub on_console_message: Option<ConsoleHandler>,
+}
+
+/// The application's init scripts and the ids WebView2 assigned to them.
+#[derive(Default)]
+struct UserScripts {
+    scripts: InitScripts,
+    added: HashMap<ScriptId, String>,
+}
+
+fn add_user_script(w: &webview2::WebView, user_scripts: &Rc<RefCell<UserScripts>>, id: ScriptId, js: &str) {
+    let (user_scripts, webview) = (user_scripts.clone(), w.clone());
+    let _ = w.add_script_to_execute_on_document_created(js, move |added| {
+        let mut s = user_scripts.borrow_mut();
+        if s.scripts.get(id).is_some() {
+            s.added.insert(id, added);
+        } else {
+            // Removed while WebView2 was adding it.
+            webview.remove_script_to_execute_on_document_created(&added)?;
+        }
+        Ok(())
+    });
+}
+
+/// Content a new controller loads.
+enum Load {
+    Url(String),
+    Html(String),
+}
+
+/// Window state shared by the window procedure, the webview's event handlers and
+/// [`WebView2`]. It outlives the controller, which is recreated when recovering from a
+/// failed browser process.
+struct Host {
+    hwnd: Cell<HWND>,
+    /// The WebView2 environment of `controller` and its generation. `None` while (re)creating it.
+    env: RefCell<Option<webview2::Environment>>,
+    generation: Cell<u64>,
+    controller: RefCell<Option<Controller>>,
+    scripts: Vec<String>,
+    user_scripts: Rc<RefCell<UserScripts>>,
+    navigation: Rc<RefCell<Navigation>>,
+    policy: Rc<RefCell<Option<NavigationPolicy>>>,
+    /// Pages that may move, resize and close the window. `None` unless it is frameless.
+    window_commands: Rc<Option<NavigationPolicy>>,
+    /// Scheme, origin and resolver of the builder's assets.
+    assets: Option<(String, String, Arc<dyn AssetResolver>)>,
+    /// The start page, or the last page loaded since.
+    current: Rc<RefCell<Load>>,
+    recovery: RefCell<Recovery>,
+    on_process_failed: RefCell<Option<ProcessFailedHandler>>,
+    error_page: RefCell<Option<ErrorPageHandler>>,
+    /// URL of the last failed navigation, for `webviewx.retry()`.
+    failed_url: Rc<RefCell<Option<String>>>,
+    /// Whether the next navigation shows one of our own pages, which the application's
+    /// navigation events and policy do not see.
+    internal: Rc<Cell<bool>>,
+    console: Rc<RefCell<Console>>,
+    /// Whether DevTools and the default context menus are enabled.
+    debug: bool,
+    /// Recovery step waiting for `RECOVERY_TIMER`.
+    step: Cell<Option<Step>>,
+    /// How creating the first controller went.
+    outcome: RefCell<Option<Result<(), String>>>,
+    /// Whether creating was cut short by closing the window or quitting.
+    closed: Cell<bool>,
+    /// Last, so the WebView2 objects above are released before an ephemeral profile waits
+    /// for the browser process to exit.
+    environment: Environment,
+}
+
+impl Host {
+    fn webview(&self) -> Option<webview2::WebView> {
+        self.controller.borrow().as_ref().and_then(|c| c.get_webview().ok())
+    }
+
+    /// Creates a controller, and the environment first if there is none.
+    fn create_controller(self: &Rc<Self>) {
+        let host = Rc::downgrade(self);
+        self.environment.get(move |env| {
+            if let Some(host) = host.upgrade() {
+                match env {
+                    Ok((env, generation)) => {
+                        *host.env.borrow_mut() = Some(env.clone());
+                        host.generation.set(generation);
+                        host.create_controller_in(&env);
+                    }
+                    Err(e) => host.created(Err(e)),
+                }
+            }
+        });
+    }
+
+    fn create_controller_in(self: &Rc<Self>, env: &webview2::Environment) {
+        let host = Rc::downgrade(self);
+        let r = env.create_controller(self.hwnd.get(), move |c| {
+            if let Some(host) = host.upgrade() {
+                let r = c.and_then(|c| host.attach(c));
+                trace_event!(debug, ok = r.is_ok(), "controller created");
+                host.created(r.map_err(|e| format!("creating the controller failed: {}", e)));
+            }
+            Ok(())
+        });
+        if let Err(e) = r {
+            self.created(Err(format!("creating the controller failed: {}", e)));
+        }
+    }
+
+    /// Records how creating a controller went. Failing to recreate one counts as another
+    /// failure of the browser process.
+    fn created(&self, result: Result<(), String>) {
+        if let Err(_e) = &result {
+            trace_event!(warn, error = %_e, "creating the webview failed");
+        }
+        if self.outcome.borrow().is_none() {
+            *self.outcome.borrow_mut() = Some(result);
+        } else if result.is_err() {
+            self.schedule(ProcessFailure::BrowserExited);
+        }
+    }
+
+    /// Sets up controller `c` and loads the current page in it.
+    fn attach(self: &Rc<Self>, c: Controller) -> webview2::Result<()> {
+        // if let Ok(c2) = c.get_controller2() {
+        //     let c = self.background_color;
+        //     c2.put_default_background_color(webview2_sys::Color {
+        //         r: c.0,
+        //         g: c.1,
+        //         b: c.2,
+        //         a: c.3,
+        //     }).unwrap();
+        // } else {
+        //     eprintln!("failed to get interface to controller2");
+        // }
+        let hwnd = self.hwnd.get();
+        let mut r = unsafe { mem::zeroed() };
+        unsafe {
+            GetClientRect(hwnd, &mut r);
+        }
+
+        c.put_bounds(r)?;
+
+        let w = c.get_webview()?;
+        if let Ok(pid) = w.get_browser_process_id() {
+            self.environment.browser_started(pid);
+        }
+        let settings = w.get_settings()?;
+        settings.put_are_dev_tools_enabled(self.debug)?;
+        settings.put_are_default_context_menus_enabled(self.debug)?;
+        for script in &self.scripts {
+            w.add_script_to_execute_on_document_created(script, |_| Ok(()))?;
+        }
+        // Including the ones added before the controller existed. Ids of a previous controller
+        // are gone with it.
+        let scripts: Vec<_> = {
+            let mut s = self.user_scripts.borrow_mut();
+            s.added.clear();
+            s.scripts.iter().map(|(id, js)| (id, js.to_owned())).collect()
+        };
+        for (id, js) in scripts {
+            add_user_script(&w, &self.user_scripts, id, &js);
+        }
+        let nav = self.navigation.clone();
+        let pol = self.policy.clone();
+        let internal = self.internal.clone();
+        w.add_navigation_starting(move |_, args| {
+            if internal.take() {
+                return Ok(())
+            }
+            let (url, user_initiated) = (args.get_uri()?, args.get_is_user_initiated()?);
+            let allowed = pol.borrow_mut().as_mut().is_none_or(|p| crate::allow_navigation(p, &PolicyRequest {
+                url: &url,
+                new_window: false,
+                user_initiated,
+            }));
+            if !allowed || nav.borrow_mut().starting(args.get_navigation_id()?, &url, user_initiated) {
+                args.put_cancel(true)?;
+            }
+            Ok(())
+        })?;
+        if self.policy.borrow().is_some() {
+            let policy = self.policy.clone();
+            w.add_new_window_requested(move |_, args| {
+                let url = args.get_uri()?;
+                let request = PolicyRequest { url: &url, new_window: true, user_initiated: args.get_is_user_initiated()? };
+                if policy.borrow_mut().as_mut().is_some_and(|p| !crate::allow_navigation(p, &request)) {
+                    args.put_handled(true)?;
+                }
+                Ok(())
+            })?;
+        }
+        let nav = self.navigation.clone();
+        w.add_content_loading(move |_, args| {
+            nav.borrow_mut().content_loading(args.get_navigation_id()?);
+            Ok(())
+        })?;
+        let nav = self.navigation.clone();
+        let current = self.current.clone();
+        let host = Rc::downgrade(self);
+        w.add_navigation_completed(move |w, args| {
+            let id = args.get_navigation_id()?;
+            let error = if args.get_is_success()? { None } else { Some(navigation_error(args.get_web_error_status()?)) };
+            let failed = error
+                .filter(|e| *e != NavigationError::Cancelled)
+                .and_then(|e| Some((nav.borrow().url(id)?.to_owned(), e)));
+            if error.is_none() {
+                // Documents loaded from strings and error pages report `about:blank`.
+                let url = w.get_source()?;
+                if crate::is_url(&url) {
+                    *current.borrow_mut() = Load::Url(url);
+                }
+            }
+            nav.borrow_mut().completed(id, error);
+            if let (Some((url, error)), Some(host)) = (failed, host.upgrade()) {
+                host.show_error_page(&w, &url, error);
+            }
+            Ok(())
+        })?;
+
+        let nav = self.navigation.clone();
+        w.add_history_changed(move |w| {
+            nav.borrow_mut().history_changed(w.get_can_go_back()?, w.get_can_go_forward()?);
+            Ok(())
+        })?;
+
+        let host = Rc::downgrade(self);
+        w.add_process_failed(move |_, args| {
+            if let Some(host) = host.upgrade() {
+                host.process_failed(process_failure(args.get_process_failed_kind()?));
+            }
+            Ok(())
+        })?;
+
+        if let (Some((_, origin, resolver)), Some(env)) = (&self.assets, self.env.borrow().clone()) {
+            let (origin, resolver) = (origin.clone(), resolver.clone());
+            w.add_web_resource_requested_filter(&format!("{}/*", origin), webview2::WebResourceContext::All)?;
+            w.add_web_resource_requested(move |_, args| {
+                let request = args.get_request()?;
+                let uri = request.get_uri()?;
+                let headers = request.get_headers()?;
+                let range = headers.get_header("Range").ok();
+                let if_none_match = headers.get_header("If-None-Match").ok();
+                let accept_encoding = headers.get_header("Accept-Encoding").ok();
+                let path = assets::request_path(&uri, &origin);
+                let res = assets::serve(&*resolver, &assets::Request {
+                    method: &request.get_method()?,
+                    path: &path,
+                    range: range.as_deref(),
+                    if_none_match: if_none_match.as_deref(),
+                    accept_encoding: accept_encoding.as_deref(),
+                });
+                let response = env.create_web_resource_response(
+                    webview2::Stream::from_bytes(&res.body),
+                    res.status as i32,
+                    res.reason,
+                    &res.header_block(),
+                )?;
+                args.put_response(response)
+            })?;
+        }
+        match &*self.current.borrow() {
+            Load::Url(url) => w.navigate(url)?,
+            Load::Html(html) => w.navigate_to_string(html)?,
+        }
+        // Receive message from webpage.
+        let navigation = self.navigation.clone();
+        let failed_url = self.failed_url.clone();
+        let console = self.console.clone();
+        let window_commands = self.window_commands.clone();
+        w.add_web_message_received(move |w, msg| {
+            let source = msg.get_source()?;
+            let msg = msg.try_get_web_message_as_string()?;
+            let _span = trace_span!(debug, "ipc", engine = "webview2", command = bridge::command(&msg), size = msg.len());
+            match msg.strip_prefix(bridge::PREFIX) {
+                Some(cmd) if cmd.starts_with("dom:") => {
+                    navigation.borrow_mut().dom_content_loaded(None);
+                    return Ok(())
+                }
+                Some(cmd) if cmd.starts_with("console:") => {
+                    console.borrow_mut().received(&cmd[8..]);
+                    return Ok(())
+                }
+                Some("retry") => {
+                    let url = failed_url.borrow().clone();
+                    return url.map_or(Ok(()), |url| w.navigate(&url))
+                }
+                _ => {}
+            }
+            if bridge::handle(hwnd, &msg, &source, (*window_commands).as_ref()) {
+                return Ok(())
+            }
+            // Send it back.
+            w.post_web_message_as_string(&msg)
+        })?;
+        *self.controller.borrow_mut() = Some(c);
+        Ok(())
+    }
+
+    /// Replaces the engine's page for the failed navigation to `url` with the application's.
+    fn show_error_page(&self, w: &webview2::WebView, url: &str, error: NavigationError) {
+        let html = match &mut *self.error_page.borrow_mut() {
+            Some(f) => f(url, error),
+            None => return
+        };
+        *self.failed_url.borrow_mut() = Some(url.to_owned());
+        self.internal.set(true);
+        let _ = w.navigate_to_string(&html);
+    }
+
+    fn process_failed(&self, failure: ProcessFailure) {
+        trace_event!(warn, failure = ?failure, "browser process failed");
+        if failure == ProcessFailure::BrowserExited {
+            // The environment went down with it.
+            self.env.borrow_mut().take();
+            self.environment.discard(self.generation.get());
+        }
+        if let Some(f) = &mut *self.on_process_failed.borrow_mut() {
+            f(failure);
+        }
+        self.schedule(failure);
+    }
+
+    /// Schedules the recovery step for `failure`, if there is one.
+    fn schedule(&self, failure: ProcessFailure) {
+        if let Some((step, delay)) = self.recovery.borrow_mut().failed(failure, Instant::now()) {
+            trace_event!(info, step = ?step, delay_ms = delay.as_millis() as u64, "recovery scheduled");
+            self.step.set(Some(step));
+            let delay = delay.as_millis().min(UINT::MAX as u128) as UINT;
+            unsafe { SetTimer(self.hwnd.get(), RECOVERY_TIMER, delay, None) };
+        }
+    }
+
+    /// Runs a step scheduled by [`schedule()`](#method.schedule).
+    fn recover(self: &Rc<Self>, step: Step) {
+        trace_event!(info, step = ?step, "recovering");
+        match step {
+            Step::Reload => {
+                if let Some(w) = self.webview() {
+                    let _ = w.reload();
+                }
+            }
+            Step::RecreateController => {
+                let old = self.controller.borrow_mut().take();
+                if let Some(c) = old {
+                    let _ = c.close();
+                }
+                self.create_controller();
+            }
+            Step::ErrorPage => {
+                let page = self.recovery.borrow().error_page().map(str::to_owned);
+                if let (Some(w), Some(page)) = (self.webview(), page) {
+                    self.internal.set(true);
+                    let _ = w.navigate_to_string(&page);
+                }
+            }
+        }
+    }
+}
+
+fn process_failure(kind: webview2::ProcessFailedKind) -> ProcessFailure {
+    use webview2::ProcessFailedKind::*;
+    match kind {
+        BrowserProcessExited => ProcessFailure::BrowserExited,
+        RenderProcessExited => ProcessFailure::RendererExited,
+        RenderProcessUnresponsive => ProcessFailure::RendererUnresponsive,
+        FrameRenderProcessExited => ProcessFailure::FrameRendererExited,
+        _ => ProcessFailure::HelperExited,
+    }
+}
+
+fn navigation_error(status: webview2::WebErrorStatus) -> NavigationError {
+    use webview2::WebErrorStatus::*;
+    match status {
+        CertificateCommonNameIsIncorrect | CertificateExpired | ClientCertificateContainsErrors
+        | CertificateRevoked | CertificateIsInvalid => NavigationError::Certificate,
+        ServerUnreachable => NavigationError::ServerUnreachable,
+        Timeout => NavigationError::Timeout,
+        ErrorHttpInvalidServerResponse => NavigationError::InvalidResponse,
+        ConnectionAborted | ConnectionReset | Disconnected => NavigationError::ConnectionLost,
+        CannotConnect => NavigationError::CannotConnect,
+        HostNameNotResolved => NavigationError::HostNameNotResolved,
+        OperationCanceled => NavigationError::Cancelled,
+        RedirectFailed => NavigationError::RedirectFailed,
+        Unknown | UnexpectedError => NavigationError::Unknown,
+    }
+}
+
 pub struct WebView2 {
     hwnd : HWND,
-    wv : Rc<OnceCell<Controller>>
+    host : Rc<Host>,
+    watcher : Option<Watcher>,
+    /// Scheme of the builder's assets.
+    scheme : Option<String>,
+    pub(crate) report : SelectionReport,
 }
 
 impl Drop for WebView2 {
@@ -394,8 +1020,154 @@ impl WebView2 {
         }
     }
 
-    pub fn loadUrl(&mut self, url:&str) {
-        //self.wv.navigate(url);
-        self.wv.get().unwrap().get_webview().unwrap().navigate(url);
+    /// Native handle of the window hosting the webview.
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    /// Moves and resizes the host window. The webview follows through `WM_SIZE`.
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        window::set_window_bounds(self.hwnd, bounds);
+    }
+
+    /// Reloads the page whenever a file below `paths` changes.
+    pub(crate) fn watch(&mut self, paths: Vec<PathBuf>) {
+        let hwnd = self.hwnd as usize;
+        self.watcher = Some(Watcher::start(paths, move || unsafe {
+            PostMessageW(hwnd as HWND, WM_RELOAD, 0, 0);
+        }));
+    }
+
+    fn webview(&self) -> Option<webview2::WebView> {
+        self.host.webview()
+    }
+
+    /// Loads `url`. `<scheme>://` URLs of the builder's assets are rewritten to their origin.
+    pub fn navigate(&mut self, url: &str) -> WVResult {
+        let url = match &self.scheme {
+            Some(scheme) => assets::rewrite_url(url, scheme, &assets::origin(scheme)),
+            None => url.to_owned()
+        };
+        match self.webview() {
+            Some(w) => w.navigate(&url).map_err(|_| WVError::Cause("navigate failed")),
+            // Loaded once the controller exists.
+            None => {
+                *self.host.current.borrow_mut() = Load::Url(url);
+                Ok(())
+            }
+        }
+    }
+
+    /// Shows `html` as a new document.
+    pub fn load_html(&mut self, html: &str) -> WVResult {
+        *self.host.current.borrow_mut() = Load::Html(html.to_owned());
+        match self.webview() {
+            Some(w) => w.navigate_to_string(html).map_err(|_| WVError::Cause("load_html failed")),
+            None => Ok(())
+        }
+    }
+
+    pub fn reload(&mut self) -> WVResult {
+        self.webview().map_or(Ok(()), |w| w.reload().map_err(|_| WVError::Cause("reload failed")))
+    }
+
+    pub fn stop(&mut self) -> WVResult {
+        self.webview().map_or(Ok(()), |w| w.stop().map_err(|_| WVError::Cause("stop failed")))
+    }
+
+    pub fn go_back(&mut self) -> WVResult {
+        self.webview().map_or(Ok(()), |w| w.go_back().map_err(|_| WVError::Cause("go_back failed")))
+    }
+
+    pub fn go_forward(&mut self) -> WVResult {
+        self.webview().map_or(Ok(()), |w| w.go_forward().map_err(|_| WVError::Cause("go_forward failed")))
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.webview().and_then(|w| w.get_can_go_back().ok()).unwrap_or(false)
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.webview().and_then(|w| w.get_can_go_forward().ok()).unwrap_or(false)
+    }
+
+    /// Runs `js` and passes the outcome to `callback`.
+    pub fn eval(&mut self, js: &str, callback: EvalCallback) {
+        let w = match self.webview() {
+            Some(w) => w,
+            None => return callback(Err(EvalError::NotReady))
+        };
+        let callback = Rc::new(Cell::new(Some(callback)));
+        let cb = callback.clone();
+        let r = w.execute_script(&script::serialized(js), move |json| {
+            // The serialized outcome comes back as a JSON string.
+            let result = serde_json::from_str::<String>(&json)
+                .map_err(|_| EvalError::Engine(format!("unexpected result {}", json)))
+                .and_then(|json| script::parse(&json));
+            if let Some(callback) = cb.take() {
+                callback(result);
+            }
+            Ok(())
+        });
+        if let Err(e) = r {
+            if let Some(callback) = callback.take() {
+                callback(Err(EvalError::Engine(e.to_string())));
+            }
+        }
+    }
+
+    /// Adds an init script that runs from the next document on.
+    pub fn add_init_script(&mut self, js: &str, main_frame_only: bool) -> ScriptId {
+        let id = self.host.user_scripts.borrow_mut().scripts.add(js, main_frame_only);
+        // Otherwise it is added with the builder's scripts once the controller exists.
+        if let Some(w) = self.webview() {
+            let js = self.host.user_scripts.borrow().scripts.get(id).unwrap_or_default().to_owned();
+            add_user_script(&w, &self.host.user_scripts, id, &js);
+        }
+        id
+    }
+
+    /// Stops running init script `id` from the next document on. Returns `false` if there is
+    /// no such script.
+    pub fn remove_init_script(&mut self, id: ScriptId) -> bool {
+        let mut s = self.host.user_scripts.borrow_mut();
+        if !s.scripts.remove(id) {
+            return false
+        }
+        if let (Some(added), Some(w)) = (s.added.remove(&id), self.webview()) {
+            let _ = w.remove_script_to_execute_on_document_created(&added);
+        }
+        true
+    }
+
+    /// Ids of the init scripts in the order they run, starting with the builder's.
+    pub fn init_scripts(&self) -> Vec<ScriptId> {
+        self.host.user_scripts.borrow().scripts.ids()
+    }
+
+    /// Opens the DevTools window, if the builder allowed them.
+    pub fn open_devtools(&mut self) -> WVResult {
+        if !self.host.debug {
+            return Err(WVError::Cause("DevTools are disabled"))
+        }
+        let w = self.webview().ok_or(WVError::Cause("the webview is not ready"))?;
+        w.open_dev_tools_window().map_err(|_| WVError::Cause("opening DevTools failed"))
+    }
+
+    /// URL of the current document, `None` before the webview is created.
+    pub fn current_url(&self) -> Option<String> {
+        self.webview().and_then(|w| w.get_source().ok())
+    }
+}
+
+unsafe impl HasRawWindowHandle for WebView2 {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        window::raw_window_handle(self.hwnd)
+    }
+}
+
+unsafe impl HasRawDisplayHandle for WebView2 {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        RawDisplayHandle::Windows(WindowsDisplayHandle::empty())
     }
 }
\ No newline at end of file